@@ -1,8 +1,14 @@
 use nannou::{
+    image::{Rgba, RgbaImage},
     noise::{NoiseFn, Perlin},
     prelude::*,
 };
-
+use ndarray::Array2;
+use vector_field::{
+    lic::{stretch, white_noise, Lic},
+    streamlines::Streamlines,
+    to_rect,
+};
 
 fn main() {
     nannou::app(model)
@@ -28,14 +34,45 @@ struct Parameters {
     loops: i32,
 }
 
+/// How the noise field is drawn
+#[derive(Copy, Clone, PartialEq)]
+enum View {
+    /// Random walkers following the field
+    Things,
+    /// Evenly spaced streamlines
+    Streamlines,
+    /// White noise smeared along the field
+    Lic,
+}
+
+impl View {
+    fn next(self) -> Self {
+        match self {
+            View::Things => View::Streamlines,
+            View::Streamlines => View::Lic,
+            View::Lic => View::Things,
+        }
+    }
+}
+
 struct Model {
     things: Vec<Thing>,
     noise: Perlin,
     parameters: Parameters,
+    view: View,
+    /// Streamlines in window coordinates
+    streamlines: Vec<Vec<Vec2>>,
+    /// Smeared along the field for the LIC view
+    lic_noise: Array2<f32>,
+    lic_image: Option<RgbaImage>,
+    /// The LIC image is uploaded to this each frame
+    texture: wgpu::Texture,
 }
 
 const N_THINGS: usize = 1000;
 const SIZE: usize = 500;
+/// Cells along each side of the sampled field
+const FIELD_SIZE: usize = 100;
 
 fn key_pressed(app: &App, model: &mut Model, key: Key) {
     match key {
@@ -48,6 +85,7 @@ fn key_pressed(app: &App, model: &mut Model, key: Key) {
                 model.parameters.sn_ratio -= 0.001;
             }
         }
+        Key::F => model.view = model.view.next(),
         Key::Right => model.parameters.speed += 1.0,
         Key::Left => {
             if model.parameters.speed > 0.0 {
@@ -65,6 +103,7 @@ fn model(app: &App) -> Model {
         .size(SIZE as u32, SIZE as u32)
         .view(view)
         .key_pressed(key_pressed)
+        .resized(resized)
         .build()
         .unwrap();
 
@@ -77,6 +116,7 @@ fn model(app: &App) -> Model {
         things.push(thing);
     }
     let noise = Perlin::new();
+    let lic_noise = lic_noise(app.window_rect());
     Model {
         things,
         noise,
@@ -85,12 +125,91 @@ fn model(app: &App) -> Model {
             sn_ratio: 0.005,
             loops: 50,
         },
+        view: View::Things,
+        streamlines: Vec::new(),
+        texture: lic_texture(app, &lic_noise),
+        lic_noise,
+        lic_image: None,
     }
 }
 
+fn lic_noise(rect: Rect) -> Array2<f32> {
+    white_noise((rect.w() as usize / 2, rect.h() as usize / 2), 0)
+}
+
+/// Texture the size of the noise, which is the size of the LIC image
+fn lic_texture(app: &App, noise: &Array2<f32>) -> wgpu::Texture {
+    let (nx, ny) = noise.dim();
+    wgpu::TextureBuilder::new()
+        .size([nx as u32, ny as u32])
+        .format(wgpu::TextureFormat::Rgba8Unorm)
+        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build(app.main_window().device())
+}
+
+fn resized(app: &App, model: &mut Model, _vec: Vec2) {
+    model.lic_noise = lic_noise(app.window_rect());
+    model.texture = lic_texture(app, &model.lic_noise);
+    model.lic_image = None;
+}
+
+/// The step a thing takes at `position`
+fn flow(noise: &Perlin, sn: f64, position: Vec2) -> Vec2 {
+    vec2(
+        noise.get([sn * position.x as f64, sn * position.y as f64, 0.0]) as f32,
+        noise.get([sn * position.x as f64, sn * position.y as f64, 1.0]) as f32,
+    )
+}
+
+/// The flow sampled at the centres of a grid over the window
+fn flow_field(noise: &Perlin, sn: f64, rect: Rect) -> Array2<Vec2> {
+    let dim = (FIELD_SIZE, FIELD_SIZE);
+    Array2::from_shape_fn(dim, |(x, y)| {
+        let centre = vec2(x as f32, y as f32) + 0.5;
+        flow(noise, sn, to_rect(centre, dim, rect))
+    })
+}
+
+/// Grey image of the values, with y going up
+fn gray_image(values: &Array2<f32>) -> RgbaImage {
+    let (nx, ny) = values.dim();
+    RgbaImage::from_fn(nx as u32, ny as u32, |x, y| {
+        let v = (values[(x as usize, ny - 1 - y as usize)] * 255.0).round() as u8;
+        Rgba([v, v, v, 255])
+    })
+}
+
 fn update(app: &App, model: &mut Model, _update: Update) {
     let time = app.elapsed_frames() as f64 / model.parameters.speed;
-    let sn =  time.cos() as f64 * model.parameters.sn_ratio;
+    let sn = time.cos() as f64 * model.parameters.sn_ratio;
+    let rect = app.window_rect();
+    match model.view {
+        View::Things => {}
+        View::Streamlines => {
+            let field = flow_field(&model.noise, sn, rect);
+            let streamlines = Streamlines {
+                separation: 2.0,
+                ..Streamlines::default()
+            };
+            model.streamlines = streamlines
+                .trace(&field, None)
+                .into_iter()
+                .map(|line| {
+                    line.into_iter()
+                        .map(|p| to_rect(p, field.dim(), rect))
+                        .collect()
+                })
+                .collect();
+            return;
+        }
+        View::Lic => {
+            let field = flow_field(&model.noise, sn, rect);
+            let mut lic = Lic::default().convolve(&field, &model.lic_noise);
+            stretch(&mut lic, 2.0);
+            model.lic_image = Some(gray_image(&lic));
+            return;
+        }
+    }
     for thing in model.things.iter_mut() {
         thing.positions.clear();
         thing.positions.push(vec2(
@@ -100,22 +219,9 @@ fn update(app: &App, model: &mut Model, _update: Update) {
 
         for _ in 0..model.parameters.loops {
             let last_position = thing.positions[0];
-            thing.positions.insert(
-                0,
-                last_position
-                    + vec2(
-                        model.noise.get([
-                            sn * last_position.x as f64,
-                            sn * last_position.y as f64,
-                            0.0,
-                        ]) as f32,
-                        model.noise.get([
-                            sn * last_position.x as f64,
-                            sn * last_position.y as f64,
-                            1.0,
-                        ]) as f32,
-                    ),
-            )
+            thing
+                .positions
+                .insert(0, last_position + flow(&model.noise, sn, last_position))
         }
     }
 }
@@ -130,10 +236,29 @@ fn view(app: &App, model: &Model, frame: Frame) {
     draw.rect()
         .w_h(SIZE as f32, SIZE as f32)
         .color(srgba(0.0, 0.0, 0.0, 0.1));
-    for thing in model.things.iter() {
-        draw.polyline()
-            .points(thing.positions.iter().cloned())
-            .color(WHITE);
+    match model.view {
+        View::Things => {
+            for thing in model.things.iter() {
+                draw.polyline()
+                    .points(thing.positions.iter().cloned())
+                    .color(WHITE);
+            }
+        }
+        View::Streamlines => {
+            for line in model.streamlines.iter() {
+                draw.polyline().points(line.iter().cloned()).color(WHITE);
+            }
+        }
+        View::Lic => {
+            if let Some(image) = &model.lic_image {
+                model.texture.upload_data(
+                    app.main_window().device(),
+                    &mut *frame.command_encoder(),
+                    image.as_flat_samples().as_slice(),
+                );
+                draw.texture(&model.texture).wh(app.window_rect().wh());
+            }
+        }
     }
 
     draw.to_frame(app, &frame).unwrap();
@@ -0,0 +1,109 @@
+//! Pictures of 2D vector fields
+//!
+//! Line integral convolution smears noise along the flow into a texture,
+//! and evenly spaced streamlines trace it with lines. Both work on any
+//! `Array2<Vec2>` indexed by `(x, y)` with y going up. Positions are in
+//! cells, cell `(x, y)` covering [x, x + 1) × [y, y + 1) with its value at
+//! the centre, so the whole field spans [0, nx] × [0, ny].
+
+use nannou::prelude::{vec2, Rect, Vec2};
+use ndarray::Array2;
+
+pub mod lic;
+pub mod streamlines;
+
+/// Shortest step traced along the flow, in cells or noise pixels. Shorter
+/// steps, including zero, negative and NaN ones, are raised to it.
+pub const MIN_STEP: f32 = 0.05;
+
+/// Bilinear interpolation of the field at `pos`, held at the outer cell
+/// centres beyond them
+pub fn sample(field: &Array2<Vec2>, pos: Vec2) -> Vec2 {
+    let (nx, ny) = field.dim();
+    let centred = pos - 0.5;
+    let clamped = vec2(
+        centred.x.clamp(0.0, (nx - 1) as f32),
+        centred.y.clamp(0.0, (ny - 1) as f32),
+    );
+    let (x0, y0) = (clamped.x as usize, clamped.y as usize);
+    let (x1, y1) = ((x0 + 1).min(nx - 1), (y0 + 1).min(ny - 1));
+    let frac = clamped - vec2(x0 as f32, y0 as f32);
+    let bottom = field[(x0, y0)].lerp(field[(x1, y0)], frac.x);
+    let top = field[(x0, y1)].lerp(field[(x1, y1)], frac.x);
+    bottom.lerp(top, frac.y)
+}
+
+/// Whether `pos` is inside a field of `dim` cells
+pub fn contains(dim: (usize, usize), pos: Vec2) -> bool {
+    (0.0..dim.0 as f32).contains(&pos.x) && (0.0..dim.1 as f32).contains(&pos.y)
+}
+
+/// Point in `rect` at `pos` in a field of `dim` cells stretched over it
+pub fn to_rect(pos: Vec2, dim: (usize, usize), rect: Rect) -> Vec2 {
+    vec2(
+        rect.left() + pos.x / dim.0 as f32 * rect.w(),
+        rect.bottom() + pos.y / dim.1 as f32 * rect.h(),
+    )
+}
+
+/// Position in cells of `point` in `rect`, the inverse of `to_rect`
+pub fn from_rect(point: Vec2, dim: (usize, usize), rect: Rect) -> Vec2 {
+    vec2(
+        (point.x - rect.left()) / rect.w() * dim.0 as f32,
+        (point.y - rect.bottom()) / rect.h() * dim.1 as f32,
+    )
+}
+
+/// Unit direction of the field at `pos`, which is in cells divided by
+/// `scale` so it can be on a finer grid than the field. None where the
+/// field is zero and the flow has no direction
+fn direction(field: &Array2<Vec2>, pos: Vec2, scale: Vec2) -> Option<Vec2> {
+    let v = sample(field, pos / scale) * scale;
+    let length = v.length();
+    (length > 0.0 && length.is_finite()).then(|| v / length)
+}
+
+/// Midpoint step of `step` along the direction, backwards if it's
+/// negative
+fn trace_step(field: &Array2<Vec2>, pos: Vec2, step: f32, scale: Vec2) -> Option<Vec2> {
+    let start = direction(field, pos, scale)?;
+    let mid = direction(field, pos + 0.5 * step * start, scale)?;
+    Some(pos + step * mid)
+}
+
+#[test]
+fn test_sample_bilinear() {
+    let field = Array2::from_shape_fn((3, 2), |(x, y)| vec2(x as f32, 10.0 * y as f32));
+    assert_eq!(sample(&field, vec2(1.5, 0.5)), vec2(1.0, 0.0));
+    assert_eq!(sample(&field, vec2(2.0, 1.0)), vec2(1.5, 5.0));
+    // held at the outer centres
+    assert_eq!(sample(&field, vec2(-4.0, 9.0)), vec2(0.0, 10.0));
+    // a single cell is constant
+    let single = Array2::from_elem((1, 1), vec2(1.0, 2.0));
+    assert_eq!(sample(&single, vec2(0.9, 0.1)), vec2(1.0, 2.0));
+
+    let rect = Rect::from_w_h(300.0, 200.0);
+    let point = to_rect(vec2(1.5, 0.5), (3, 2), rect);
+    assert_eq!(point, vec2(0.0, -50.0));
+    assert_eq!(from_rect(point, (3, 2), rect), vec2(1.5, 0.5));
+    assert!(contains((3, 2), vec2(2.9, 0.0)) && !contains((3, 2), vec2(3.0, 1.0)));
+}
+
+#[test]
+fn test_trace_step_follows_circles() {
+    // rotation about the middle, a step at a time stays on its circle
+    let field = Array2::from_shape_fn((40, 40), |(x, y)| {
+        let r = vec2(x as f32 + 0.5, y as f32 + 0.5) - 20.0;
+        vec2(-r.y, r.x)
+    });
+    let mut pos = vec2(30.0, 20.0);
+    for _ in 0..200 {
+        pos = trace_step(&field, pos, 0.25, Vec2::ONE).unwrap();
+    }
+    assert!(
+        (pos.distance(vec2(20.0, 20.0)) - 10.0).abs() < 0.05,
+        "{}",
+        pos
+    );
+    assert_eq!(direction(&field, vec2(20.0, 20.0), Vec2::ONE), None);
+}
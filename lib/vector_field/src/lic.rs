@@ -0,0 +1,221 @@
+//! Line integral convolution, from Cabral and Leedom's "Imaging Vector
+//! Fields Using Line Integral Convolution"
+//!
+//! Every pixel averages the noise along the streamline through it, so
+//! pixels on the same streamline come out alike and neighbouring ones
+//! don't, which draws the flow as streaks. The noise sets the resolution
+//! and can be finer than the field, which is sampled in between.
+
+use nannou::prelude::{vec2, Vec2};
+use ndarray::Array2;
+use rayon::prelude::*;
+
+use crate::{contains, trace_step, MIN_STEP};
+
+/// Weight along the streamline
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Kernel {
+    /// Even weights, the sharpest streaks
+    Box,
+    /// Fading to zero at both ends, smoother when the field changes
+    #[default]
+    Hann,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 2] = [Kernel::Box, Kernel::Hann];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::Box => "box",
+            Kernel::Hann => "hann",
+        }
+    }
+
+    /// Weight at `t` from the middle in [0, 1] to the end
+    fn weight(&self, t: f32) -> f32 {
+        match self {
+            Kernel::Box => 1.0,
+            Kernel::Hann => 0.5 + 0.5 * (std::f32::consts::PI * t).cos(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lic {
+    /// Distance traced each way from a pixel, in noise pixels
+    pub length: f32,
+    /// Distance between samples along the streamline, in noise pixels, at
+    /// least [`MIN_STEP`]
+    pub step: f32,
+    pub kernel: Kernel,
+}
+
+impl Default for Lic {
+    fn default() -> Self {
+        Self {
+            length: 10.0,
+            step: 1.0,
+            kernel: Kernel::default(),
+        }
+    }
+}
+
+impl Lic {
+    /// The noise convolved along `field`, which is stretched over the
+    /// noise so the result has the noise's size
+    pub fn convolve(&self, field: &Array2<Vec2>, noise: &Array2<f32>) -> Array2<f32> {
+        let (nx, ny) = noise.dim();
+        let (fx, fy) = field.dim();
+        let scale = vec2(nx as f32 / fx as f32, ny as f32 / fy as f32);
+        let step = self.step.max(MIN_STEP);
+        let steps = (self.length / step).round().max(1.0) as usize;
+        let mut out = Array2::zeros((nx, ny));
+        if nx * ny == 0 || fx * fy == 0 {
+            return out;
+        }
+        out.as_slice_mut()
+            .expect("new arrays are in standard layout")
+            .par_chunks_mut(ny)
+            .enumerate()
+            .for_each(|(x, row)| {
+                for (y, cell) in row.iter_mut().enumerate() {
+                    let centre = vec2(x as f32, y as f32) + 0.5;
+                    let mut sum = self.kernel.weight(0.0) * pixel(noise, centre);
+                    let mut total = self.kernel.weight(0.0);
+                    for step in [step, -step] {
+                        let mut pos = centre;
+                        for i in 1..=steps {
+                            pos = match trace_step(field, pos, step, scale) {
+                                Some(next) if contains((nx, ny), next) => next,
+                                _ => break,
+                            };
+                            let weight = self.kernel.weight(i as f32 / (steps + 1) as f32);
+                            sum += weight * pixel(noise, pos);
+                            total += weight;
+                        }
+                    }
+                    *cell = sum / total;
+                }
+            });
+        out
+    }
+}
+
+/// The noise pixel under `pos`
+fn pixel(noise: &Array2<f32>, pos: Vec2) -> f32 {
+    let (nx, ny) = noise.dim();
+    noise[(
+        (pos.x.max(0.0) as usize).min(nx - 1),
+        (pos.y.max(0.0) as usize).min(ny - 1),
+    )]
+}
+
+/// Uniform noise in [0, 1) that's the same for the same seed
+pub fn white_noise(dim: (usize, usize), seed: u64) -> Array2<f32> {
+    let mut index = 0u64;
+    Array2::from_shape_simple_fn(dim, || {
+        index += 1;
+        // splitmix64 of the seed and the position
+        let mut z = seed.wrapping_add(index).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    })
+}
+
+/// Spread the values out around their mean, so that `spread` standard
+/// deviations either side reach 0 and 1, and clamp them to [0, 1].
+/// Averaging flattens the noise towards grey, and this brings back the
+/// contrast
+pub fn stretch(values: &mut Array2<f32>, spread: f32) {
+    let count = values.len().max(1) as f32;
+    let mean = values.sum() / count;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+    let width = 2.0 * spread * variance.sqrt();
+    values.mapv_inplace(|v| {
+        if width > 0.0 {
+            ((v - mean) / width + 0.5).clamp(0.0, 1.0)
+        } else {
+            0.5
+        }
+    });
+}
+
+/// Mean size of the change from each pixel to the next one along `offset`
+#[cfg(test)]
+fn roughness(values: &Array2<f32>, offset: (usize, usize)) -> f32 {
+    let (nx, ny) = values.dim();
+    let mut total = 0.0;
+    let mut count = 0;
+    for x in 5..nx - 5 {
+        for y in 5..ny - 5 {
+            total += (values[(x + offset.0, y + offset.1)] - values[(x, y)]).abs();
+            count += 1;
+        }
+    }
+    total / count as f32
+}
+
+#[test]
+fn test_white_noise() {
+    let noise = white_noise((64, 64), 7);
+    assert_eq!(noise, white_noise((64, 64), 7));
+    assert_ne!(noise, white_noise((64, 64), 8));
+    assert!(noise.iter().all(|v| (0.0..1.0).contains(v)));
+    let mean = noise.sum() / noise.len() as f32;
+    assert!((mean - 0.5).abs() < 0.02, "{}", mean);
+}
+
+#[test]
+fn test_lic_smears_along_the_flow() {
+    let noise = white_noise((60, 60), 1);
+    // flowing right on a coarser grid than the noise
+    let field = Array2::from_elem((20, 30), vec2(2.0, 0.0));
+    for kernel in Kernel::ALL {
+        let lic = Lic {
+            kernel,
+            ..Lic::default()
+        };
+        let mut out = lic.convolve(&field, &noise);
+        assert_eq!(out.dim(), (60, 60));
+        stretch(&mut out, 2.0);
+        let along = roughness(&out, (1, 0));
+        let across = roughness(&out, (0, 1));
+        assert!(along < 0.3 * across, "{:?} {} {}", kernel, along, across);
+    }
+
+    // no flow leaves the noise as it is
+    let still = Array2::from_elem((20, 30), Vec2::ZERO);
+    assert_eq!(Lic::default().convolve(&still, &noise), noise);
+}
+
+#[test]
+fn test_lic_raises_short_steps() {
+    let noise = white_noise((30, 30), 3);
+    let field = Array2::from_elem((10, 10), vec2(1.0, 0.0));
+    let expected = Lic {
+        step: MIN_STEP,
+        ..Lic::default()
+    }
+    .convolve(&field, &noise);
+    for step in [0.0, -1.0, f32::NAN] {
+        let lic = Lic {
+            step,
+            ..Lic::default()
+        };
+        assert_eq!(lic.convolve(&field, &noise), expected, "{}", step);
+    }
+}
+
+#[test]
+fn test_stretch() {
+    let mut values = Array2::from_shape_fn((2, 2), |(x, y)| 0.4 + 0.1 * (x + y) as f32);
+    stretch(&mut values, 1.0);
+    assert!((values[(0, 1)] - 0.5).abs() < 1e-5);
+    assert!(values[(0, 0)] < 0.2 && values[(1, 1)] > 0.8);
+    let mut flat = Array2::from_elem((2, 2), 0.3);
+    stretch(&mut flat, 1.0);
+    assert!(flat.iter().all(|&v| v == 0.5));
+}
@@ -0,0 +1,299 @@
+//! Evenly spaced streamlines, from Jobard and Lefer's "Creating Evenly
+//! Spaced Streamlines of Arbitrary Density"
+//!
+//! Each streamline is traced both ways from a seed until it comes too
+//! close to another one, and new seeds are tried a separation away on
+//! either side of every point of the accepted lines, so the lines fill
+//! the field one beside the next. Seeds on a lattice catch the regions
+//! that can't be reached that way.
+
+use nannou::prelude::Vec2;
+use ndarray::Array2;
+
+use crate::{contains, direction, trace_step, MIN_STEP};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Streamlines {
+    /// Distance between neighbouring streamlines, in cells, at least the
+    /// step
+    pub separation: f32,
+    /// Fraction of the separation a streamline gets to another before it
+    /// stops
+    pub test: f32,
+    /// Distance between points along a streamline, in cells, at least
+    /// [`MIN_STEP`]
+    pub step: f32,
+    /// Shorter streamlines are left out
+    pub min_points: usize,
+    /// Longest streamline, which stops closed loops going round forever
+    pub max_points: usize,
+}
+
+impl Default for Streamlines {
+    fn default() -> Self {
+        Self {
+            separation: 3.0,
+            test: 0.5,
+            step: 0.5,
+            min_points: 5,
+            max_points: 2000,
+        }
+    }
+}
+
+/// Points of the streamlines in buckets a separation wide, each with
+/// the streamline it's on and its place along it
+struct Buckets {
+    size: f32,
+    dim: (usize, usize),
+    points: Vec<Vec<(Vec2, usize, isize)>>,
+}
+
+impl Buckets {
+    fn new(field_dim: (usize, usize), size: f32) -> Self {
+        let dim = (
+            (field_dim.0 as f32 / size).ceil() as usize + 1,
+            (field_dim.1 as f32 / size).ceil() as usize + 1,
+        );
+        Self {
+            size,
+            dim,
+            points: vec![Vec::new(); dim.0 * dim.1],
+        }
+    }
+
+    fn bucket(&self, pos: Vec2) -> (usize, usize) {
+        (
+            ((pos.x / self.size).max(0.0) as usize).min(self.dim.0 - 1),
+            ((pos.y / self.size).max(0.0) as usize).min(self.dim.1 - 1),
+        )
+    }
+
+    fn insert(&mut self, pos: Vec2, line: usize, place: isize) {
+        let (bx, by) = self.bucket(pos);
+        self.points[bx * self.dim.1 + by].push((pos, line, place));
+    }
+
+    fn remove(&mut self, line: usize) {
+        for bucket in &mut self.points {
+            bucket.retain(|&(_, l, _)| l != line);
+        }
+    }
+
+    /// Whether a point closer than `distance`, which is at most the
+    /// bucket size, is on another streamline or on `line` more than
+    /// `skip` places from `place`
+    fn crowded(&self, pos: Vec2, distance: f32, line: usize, place: isize, skip: isize) -> bool {
+        let (bx, by) = self.bucket(pos);
+        let xs = bx.saturating_sub(1)..(bx + 2).min(self.dim.0);
+        xs.flat_map(|x| {
+            let ys = by.saturating_sub(1)..(by + 2).min(self.dim.1);
+            ys.map(move |y| x * self.dim.1 + y)
+        })
+        .flat_map(|bucket| &self.points[bucket])
+        .any(|&(p, l, i)| {
+            (l != line || (i - place).abs() > skip) && p.distance_squared(pos) < distance * distance
+        })
+    }
+}
+
+impl Streamlines {
+    /// The step and separation actually used, raised to their minimums so
+    /// the lattice and the buckets are never empty
+    fn spacing(&self) -> (f32, f32) {
+        let step = self.step.max(MIN_STEP);
+        (step, self.separation.max(step))
+    }
+
+    /// Streamlines of the field as points in cells, skipping the cells in
+    /// `solid` if there is one, which has the field's size
+    pub fn trace(&self, field: &Array2<Vec2>, solid: Option<&Array2<bool>>) -> Vec<Vec<Vec2>> {
+        let dim = field.dim();
+        let (_, separation) = self.spacing();
+        let open = |pos: Vec2| {
+            contains(dim, pos)
+                && match solid {
+                    Some(solid) => !solid[(pos.x as usize, pos.y as usize)],
+                    None => true,
+                }
+        };
+        let mut buckets = Buckets::new(dim, separation);
+        let mut lines: Vec<Vec<Vec2>> = Vec::new();
+        // lattice seeds for regions the lines haven't reached
+        let mut lattice = (0..)
+            .map(|i| (i as f32 + 0.5) * separation)
+            .take_while(|&x| x < dim.0 as f32)
+            .flat_map(|x| {
+                (0..)
+                    .map(|j| (j as f32 + 0.5) * separation)
+                    .take_while(|&y| y < dim.1 as f32)
+                    .map(move |y| Vec2::new(x, y))
+            });
+        let mut next = 0;
+        loop {
+            let seeds: Vec<Vec2> = if let Some(line) = lines.get(next) {
+                next += 1;
+                line.iter()
+                    .filter_map(|&p| {
+                        let normal = direction(field, p, Vec2::ONE)?.perp();
+                        Some([p + separation * normal, p - separation * normal])
+                    })
+                    .flatten()
+                    .collect()
+            } else if let Some(seed) = lattice.next() {
+                vec![seed]
+            } else {
+                break;
+            };
+            for seed in seeds {
+                if !open(seed)
+                    || direction(field, seed, Vec2::ONE).is_none()
+                    || buckets.crowded(seed, separation, usize::MAX, 0, 0)
+                {
+                    continue;
+                }
+                let id = lines.len();
+                let line = self.integrate(field, seed, id, &mut buckets, &open);
+                if line.len() >= self.min_points.max(1) {
+                    lines.push(line);
+                } else {
+                    buckets.remove(id);
+                }
+            }
+        }
+        lines
+    }
+
+    /// The streamline through `seed`, with its points added to the
+    /// buckets as line `id`
+    fn integrate(
+        &self,
+        field: &Array2<Vec2>,
+        seed: Vec2,
+        id: usize,
+        buckets: &mut Buckets,
+        open: &impl Fn(Vec2) -> bool,
+    ) -> Vec<Vec2> {
+        let (step, separation) = self.spacing();
+        let distance = self.test * separation;
+        // own points nearer than this along the line don't count as close
+        let skip = (2.0 * separation / step).ceil() as isize;
+        let mut forward = vec![seed];
+        let mut backward = Vec::new();
+        let mut count = 1;
+        buckets.insert(seed, id, 0);
+        for (step, points, sign) in [(step, &mut forward, 1), (-step, &mut backward, -1)] {
+            let mut pos = seed;
+            let mut place = 0;
+            while count < self.max_points {
+                pos = match trace_step(field, pos, step, Vec2::ONE) {
+                    Some(next) if open(next) => next,
+                    _ => break,
+                };
+                place += sign;
+                if buckets.crowded(pos, distance, id, place, skip) {
+                    break;
+                }
+                buckets.insert(pos, id, place);
+                points.push(pos);
+                count += 1;
+            }
+        }
+        backward.reverse();
+        backward.extend(forward);
+        backward
+    }
+}
+
+#[test]
+fn test_uniform_flow_gives_parallel_lines() {
+    let field = Array2::from_elem((40, 30), Vec2::new(1.0, 0.0));
+    let streamlines = Streamlines::default();
+    let lines = streamlines.trace(&field, None);
+    let mut heights: Vec<f32> = lines.iter().map(|line| line[0].y).collect();
+    heights.sort_by(f32::total_cmp);
+    assert_eq!(heights.len(), 10, "{:?}", heights);
+    for (line, &y) in lines.iter().zip(&heights) {
+        assert!(line.iter().all(|p| (p.y - line[0].y).abs() < 1e-4));
+        assert!(line.first().unwrap().x < 0.5 && line.last().unwrap().x >= 39.5);
+        assert!(line.windows(2).all(|w| w[1].x > w[0].x), "{}", y);
+    }
+    for pair in heights.windows(2) {
+        assert!((pair[1] - pair[0] - 3.0).abs() < 1e-3, "{:?}", heights);
+    }
+}
+
+#[test]
+fn test_lines_keep_apart_and_fill_the_field() {
+    // a vortex, the lines are closed circles
+    let field = Array2::from_shape_fn((40, 40), |(x, y)| {
+        let r = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - 20.0;
+        r.perp()
+    });
+    let streamlines = Streamlines {
+        separation: 2.0,
+        ..Streamlines::default()
+    };
+    let lines = streamlines.trace(&field, None);
+    assert!(lines.len() > 5);
+    let closest = streamlines.test * streamlines.separation - 1e-3;
+    for (i, line) in lines.iter().enumerate() {
+        assert!(line.len() >= streamlines.min_points && line.len() <= streamlines.max_points);
+        for other in &lines[i + 1..] {
+            for p in line {
+                assert!(other.iter().all(|q| p.distance(*q) >= closest));
+            }
+        }
+    }
+    // nowhere away from the edges and the centre is far from a line
+    for x in 4..36 {
+        for y in 4..36 {
+            let pos = Vec2::new(x as f32, y as f32);
+            if pos.distance(Vec2::splat(20.0)) < 3.0 {
+                continue;
+            }
+            let nearest = lines
+                .iter()
+                .flatten()
+                .map(|p| p.distance(pos))
+                .fold(f32::MAX, f32::min);
+            assert!(
+                nearest < 2.0 * streamlines.separation,
+                "{} {}",
+                pos,
+                nearest
+            );
+        }
+    }
+}
+
+#[test]
+fn test_lines_avoid_solid_cells() {
+    let field = Array2::from_elem((30, 30), Vec2::new(1.0, 0.0));
+    let solid = Array2::from_shape_fn((30, 30), |(x, _)| (10..20).contains(&x));
+    let lines = Streamlines::default().trace(&field, Some(&solid));
+    assert!(!lines.is_empty());
+    for p in lines.iter().flatten() {
+        assert!(!solid[(p.x as usize, p.y as usize)], "{}", p);
+    }
+    // lines on both sides of the wall
+    assert!(lines.iter().flatten().any(|p| p.x < 10.0));
+    assert!(lines.iter().flatten().any(|p| p.x >= 20.0));
+}
+
+#[test]
+fn test_short_spacing_is_raised() {
+    let field = Array2::from_elem((20, 20), Vec2::new(1.0, 0.0));
+    for (separation, step) in [(0.0, 0.5), (3.0, 0.0), (-1.0, -1.0), (f32::NAN, f32::NAN)] {
+        let streamlines = Streamlines {
+            separation,
+            step,
+            ..Streamlines::default()
+        };
+        let lines = streamlines.trace(&field, None);
+        assert!(!lines.is_empty(), "{} {}", separation, step);
+        assert!(lines
+            .iter()
+            .all(|line| line.len() <= streamlines.max_points));
+    }
+}
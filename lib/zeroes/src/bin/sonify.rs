@@ -0,0 +1,116 @@
+//! Render a range of zeroes to a WAV file
+//!
+//! cargo run --bin sonify -- --from 1 --to 200 --source spacing --mapping pitch --out zeroes.wav
+//!
+//! Zeroes are read from `--file` (the bundled `zeros10000.txt` by default)
+//! or computed with `--compute`, which can go past the end of the file.
+
+use std::{env, process};
+
+use zeroes::{
+    loader::Format,
+    sonify::{Mapping, Sonification, Source},
+    Zero, Zeroes,
+};
+
+struct Args {
+    file: String,
+    compute: bool,
+    from: u64,
+    to: u64,
+    out: String,
+    sonification: Sonification,
+}
+
+const USAGE: &str = "usage: sonify [--file zeros10000.txt] [--compute] [--from 1] [--to 100] \
+[--source height|fract|spacing] [--mapping pitch|duration|partials] \
+[--rate 44100] [--tempo 240] [--low 110] [--high 880] [--out zeroes.wav]";
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        file: "zeros10000.txt".to_string(),
+        compute: false,
+        from: 1,
+        to: 100,
+        out: "zeroes.wav".to_string(),
+        sonification: Sonification::default(),
+    };
+    let mut iter = env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        if flag == "--compute" {
+            args.compute = true;
+            continue;
+        }
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let number = |v: &str| v.parse::<f32>().map_err(|e| format!("{}: {}", flag, e));
+        let ordinal = |v: &str| v.parse::<u64>().map_err(|e| format!("{}: {}", flag, e));
+        let s = &mut args.sonification;
+        match flag.as_str() {
+            "--file" => args.file = value,
+            "--from" => args.from = ordinal(&value)?,
+            "--to" => args.to = ordinal(&value)?,
+            "--out" => args.out = value,
+            "--source" => s.source = value.parse::<Source>()?,
+            "--mapping" => s.mapping = value.parse::<Mapping>()?,
+            "--rate" => s.sample_rate = number(&value)? as u32,
+            "--tempo" => s.tempo = number(&value)?,
+            "--low" => s.low_hz = number(&value)?,
+            "--high" => s.high_hz = number(&value)?,
+            other => return Err(format!("unknown flag {}", other)),
+        }
+    }
+    if args.from == 0 || args.to < args.from {
+        return Err("need 1 <= from <= to".to_string());
+    }
+    let s = &args.sonification;
+    if s.sample_rate == 0 {
+        return Err("need a rate above 0".to_string());
+    }
+    if !(s.tempo.is_finite() && s.tempo > 0.0) {
+        return Err("need a tempo above 0".to_string());
+    }
+    if !(s.low_hz.is_finite() && s.high_hz.is_finite()) || s.low_hz <= 0.0 || s.low_hz >= s.high_hz
+    {
+        return Err("need 0 < low < high".to_string());
+    }
+    Ok(args)
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(1);
+    });
+
+    let zeroes = if args.compute {
+        Zeroes::compute_first(args.to as usize, 1e-10).unwrap_or_else(|e| {
+            eprintln!("can't compute zeroes: {}", e);
+            process::exit(1);
+        })
+    } else {
+        Zeroes::load_from(&args.file, Format::Indexed).unwrap_or_else(|e| {
+            eprintln!("can't load {}: {}", args.file, e);
+            process::exit(1);
+        })
+    };
+    let range: Vec<Zero> = zeroes
+        .iter()
+        .filter(|z| (args.from..=args.to).contains(&z.pos()))
+        .cloned()
+        .collect();
+    if range.is_empty() {
+        eprintln!("no zeroes between {} and {}", args.from, args.to);
+        process::exit(1);
+    }
+
+    println!(
+        "writing zeroes {} to {} as {:?} {:?} to {}",
+        args.from, args.to, args.sonification.source, args.sonification.mapping, args.out
+    );
+    if let Err(e) = args.sonification.write_wav(&range, &args.out) {
+        eprintln!("can't write {}: {}", args.out, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,285 @@
+//! Compact binary cache of a zero table, read through a memory map
+//!
+//! Each zero is stored as a little endian `u64` holding its height above
+//! an integer offset, in fixed point with a chosen number of decimal
+//! digits. That is 8 bytes a zero instead of a `BigDecimal`, and lookups
+//! don't need the file to be read in at all.
+//!
+//! The header is 48 bytes: the magic `ZEROCACH`, the format version and
+//! digit count as `u32`, the position of the first zero and the number
+//! of zeroes as `u64`, then the offset as `u128`. Positions are
+//! consecutive from the first.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use bigdecimal::{BigDecimal, ToPrimitive};
+use memmap2::Mmap;
+use num_bigint::BigInt;
+
+use crate::{loader::LoadError, Zero, Zeroes};
+
+const MAGIC: &[u8; 8] = b"ZEROCACH";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 48;
+
+/// Decimal digits kept by default, enough for the bundled table and for
+/// heights up to about 10^7
+pub const DEFAULT_DIGITS: u32 = 12;
+
+pub struct ZeroCache {
+    mmap: Mmap,
+    digits: u32,
+    first_pos: u64,
+    count: usize,
+    offset: u128,
+}
+
+fn cache_error(msg: &str) -> LoadError {
+    LoadError::Cache(msg.to_string())
+}
+
+fn ten_pow(digits: u32) -> BigDecimal {
+    BigDecimal::from(BigInt::from(10).pow(digits))
+}
+
+impl ZeroCache {
+    /// Write the zeroes to a cache file, keeping `digits` decimal places.
+    /// The zeroes are streamed, so this works straight from a
+    /// [`crate::loader::ZeroReader`] on a huge table. Returns how many
+    /// zeroes were written.
+    pub fn write<P, I>(path: P, zeroes: I, digits: u32) -> Result<u64, LoadError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = Result<Zero, LoadError>>,
+    {
+        let mut out = BufWriter::new(File::create(path)?);
+        // header is rewritten once the count and offset are known
+        out.write_all(&[0; HEADER_LEN])?;
+
+        let scale = ten_pow(digits);
+        let mut first_pos = 0;
+        let mut offset = 0u128;
+        let mut offset_dec = BigDecimal::from(0);
+        let mut count = 0u64;
+        for zero in zeroes {
+            let zero = zero?;
+            if count == 0 {
+                first_pos = zero.pos;
+                let (int_part, _) = zero.value().with_scale(0).into_bigint_and_exponent();
+                offset = int_part
+                    .to_u128()
+                    .ok_or_else(|| cache_error("negative or huge zero"))?;
+                offset_dec = BigDecimal::from(int_part);
+            } else if zero.pos != first_pos + count {
+                return Err(cache_error("zero positions must be consecutive"));
+            }
+            let raw = ((zero.value() - &offset_dec) * &scale)
+                .round(0)
+                .to_u64()
+                .ok_or_else(|| cache_error("zero too far above offset for digits"))?;
+            out.write_all(&raw.to_le_bytes())?;
+            count += 1;
+        }
+
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&digits.to_le_bytes())?;
+        out.write_all(&first_pos.to_le_bytes())?;
+        out.write_all(&count.to_le_bytes())?;
+        out.write_all(&offset.to_le_bytes())?;
+        out.flush()?;
+        Ok(count)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let file = File::open(path)?;
+        // Safety: the cache is only read, and isn't expected to be
+        // modified while mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < HEADER_LEN || &mmap[..8] != MAGIC {
+            return Err(cache_error("missing header"));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(mmap[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(mmap[i..i + 8].try_into().unwrap());
+        if u32_at(8) != VERSION {
+            return Err(cache_error("unknown version"));
+        }
+        let digits = u32_at(12);
+        let first_pos = u64_at(16);
+        let count = usize::try_from(u64_at(24)).map_err(|_| cache_error("too many zeroes"))?;
+        let offset = u128::from_le_bytes(mmap[32..48].try_into().unwrap());
+        let len = count
+            .checked_mul(8)
+            .and_then(|bytes| bytes.checked_add(HEADER_LEN));
+        if len != Some(mmap.len()) {
+            return Err(cache_error("length doesn't match zero count"));
+        }
+        if first_pos.checked_add(count as u64).is_none() {
+            return Err(cache_error("positions past the last u64"));
+        }
+        Ok(Self {
+            mmap,
+            digits,
+            first_pos,
+            count,
+            offset,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Position of the first zero in the cache
+    pub fn first_pos(&self) -> u64 {
+        self.first_pos
+    }
+
+    /// Fixed point height of the zero at `index`, which must be below
+    /// `len`
+    fn raw(&self, index: usize) -> u64 {
+        let bytes = index
+            .checked_mul(8)
+            .and_then(|offset| offset.checked_add(HEADER_LEN))
+            .filter(|_| index < self.count)
+            .and_then(|start| self.mmap.get(start..start + 8))
+            .unwrap_or_else(|| panic!("index {} of {} zeroes", index, self.count));
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    /// Height above the cache's integer offset. For the high tables this
+    /// keeps the precision that `height` loses.
+    pub fn offset_height(&self, index: usize) -> f64 {
+        self.raw(index) as f64 / 10f64.powi(self.digits as i32)
+    }
+
+    /// Height of the zero at `index` (0 based) as a float
+    pub fn height(&self, index: usize) -> f64 {
+        self.offset as f64 + self.offset_height(index)
+    }
+
+    /// Height of the zero at `index` at the stored precision
+    pub fn value(&self, index: usize) -> BigDecimal {
+        BigDecimal::new(BigInt::from(self.raw(index)), self.digits as i64)
+            + BigDecimal::from(BigInt::from(self.offset))
+    }
+
+    pub fn zero(&self, index: usize) -> Zero {
+        Zero::new(self.first_pos + index as u64, self.value(index))
+    }
+
+    /// Zero with ordinal `pos`
+    pub fn get(&self, pos: u64) -> Option<Zero> {
+        let index = pos.checked_sub(self.first_pos)? as usize;
+        (index < self.count).then(|| self.zero(index))
+    }
+
+    /// Number of zeroes in the cache with height at most `t`
+    pub fn count_up_to(&self, t: f64) -> usize {
+        let target = (t - self.offset as f64) * 10f64.powi(self.digits as i32);
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if (self.raw(mid) as f64) <= target {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    pub fn heights(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.count).map(move |i| self.height(i))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Zero> + '_ {
+        (0..self.count).map(move |i| self.zero(i))
+    }
+}
+
+impl Zeroes {
+    /// Write these zeroes to a binary cache, see [`ZeroCache::write`]
+    pub fn write_cache<P: AsRef<Path>>(&self, path: P, digits: u32) -> Result<u64, LoadError> {
+        ZeroCache::write(path, self.zeroes.iter().cloned().map(Ok), digits)
+    }
+}
+
+#[test]
+fn test_cache_round_trip() {
+    let zeroes = Zeroes::load();
+    let path = std::env::temp_dir().join("zeroes_test_cache_round_trip.bin");
+    assert_eq!(zeroes.write_cache(&path, DEFAULT_DIGITS).unwrap(), 10000);
+
+    let cache = ZeroCache::open(&path).unwrap();
+    assert_eq!(cache.len(), 10000);
+    assert_eq!(cache.first_pos(), 1);
+    for (cached, zero) in cache.iter().zip(zeroes.iter()) {
+        assert_eq!(cached.pos(), zero.pos());
+        assert!((cached.value() - zero.value()).abs() < BigDecimal::new(1.into(), 12));
+    }
+    assert_eq!(cache.get(3).unwrap().int_val(), Some(25));
+    assert!(cache.get(10001).is_none());
+    assert_eq!(cache.count_up_to(100.0), zeroes.count_up_to(100.0));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_cache_high_table() {
+    use crate::loader::{Format, ZeroReader};
+    use std::str::FromStr;
+
+    let text = "0.20180528\n0.39869513\n0.71054553\n";
+    let format = Format::Offset {
+        first_pos: 1_000_000_000_001,
+        offset: BigDecimal::from_str("1370919909931995300000").unwrap(),
+    };
+    let path = std::env::temp_dir().join("zeroes_test_cache_high_table.bin");
+    ZeroCache::write(&path, ZeroReader::new(text.as_bytes(), format), 9).unwrap();
+
+    let cache = ZeroCache::open(&path).unwrap();
+    assert_eq!(cache.len(), 3);
+    assert_eq!(
+        cache.value(1),
+        BigDecimal::from_str("1370919909931995300000.39869513").unwrap()
+    );
+    assert!((cache.offset_height(2) - 0.71054553).abs() < 1e-12);
+    assert_eq!(
+        cache.get(1_000_000_000_003).unwrap().pos(),
+        1_000_000_000_003
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_corrupt_count_is_an_error() {
+    let zeroes = Zeroes::load();
+    let path = std::env::temp_dir().join("zeroes_test_corrupt_count.bin");
+    for (at, value) in [
+        (24, u64::MAX),
+        (24, u64::MAX / 8 + 1),
+        (24, 3),
+        (16, u64::MAX),
+    ] {
+        zeroes.write_cache(&path, DEFAULT_DIGITS).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        assert!(
+            matches!(ZeroCache::open(&path), Err(LoadError::Cache(_))),
+            "{} {}",
+            at,
+            value
+        );
+    }
+    std::fs::remove_file(path).unwrap();
+}
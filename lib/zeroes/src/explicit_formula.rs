@@ -0,0 +1,333 @@
+//! Riemann's explicit formula, rebuilding the prime counting functions
+//! from the first K zeroes, with an exact sieve to compare against.
+
+use std::f64::consts::{LN_2, PI};
+
+use crate::stats::Series;
+
+/// Euler-Mascheroni constant
+const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+/// Evenly spaced points of x at which to evaluate the formulas
+#[derive(Copy, Clone, Debug)]
+pub struct Sampling {
+    pub start: f64,
+    pub end: f64,
+    pub samples: usize,
+}
+
+impl Sampling {
+    pub fn new(start: f64, end: f64, samples: usize) -> Self {
+        Self {
+            start,
+            end,
+            samples,
+        }
+    }
+
+    pub fn points(&self) -> impl Iterator<Item = f64> + '_ {
+        let step = (self.end - self.start) / self.samples.saturating_sub(1).max(1) as f64;
+        (0..self.samples).map(move |i| self.start + step * i as f64)
+    }
+}
+
+/// Which prime counting function to rebuild
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PrimeFunction {
+    /// Chebyshev's psi, the sum of log p over prime powers p^k <= x
+    Psi,
+    /// pi, the number of primes <= x
+    Pi,
+}
+
+/// Sieve of Eratosthenes for exact values of pi(x) and psi(x)
+pub struct PrimeSieve {
+    is_prime: Vec<bool>,
+}
+
+impl PrimeSieve {
+    pub fn new(limit: usize) -> Self {
+        let mut is_prime = vec![true; limit + 1];
+        is_prime.iter_mut().take(2).for_each(|p| *p = false);
+        let mut i = 2;
+        while i * i <= limit {
+            if is_prime[i] {
+                (i * i..=limit).step_by(i).for_each(|j| is_prime[j] = false);
+            }
+            i += 1;
+        }
+        Self { is_prime }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.is_prime.len() - 1
+    }
+
+    pub fn is_prime(&self, n: usize) -> bool {
+        self.is_prime.get(n).copied().unwrap_or(false)
+    }
+
+    pub fn primes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.is_prime
+            .iter()
+            .enumerate()
+            .filter(|(_, &p)| p)
+            .map(|(n, _)| n)
+    }
+
+    /// Number of primes at most `x`
+    pub fn pi(&self, x: f64) -> f64 {
+        self.primes().take_while(|&p| p as f64 <= x).count() as f64
+    }
+
+    /// Chebyshev's psi, the sum of log p over prime powers at most `x`
+    pub fn psi(&self, x: f64) -> f64 {
+        self.primes()
+            .take_while(|&p| p as f64 <= x)
+            .map(|p| {
+                let ln_p = (p as f64).ln();
+                // number of powers of p at most x
+                (x.ln() / ln_p + 1e-12).floor() * ln_p
+            })
+            .sum()
+    }
+
+    pub fn eval(&self, function: PrimeFunction, x: f64) -> f64 {
+        match function {
+            PrimeFunction::Psi => self.psi(x),
+            PrimeFunction::Pi => self.pi(x),
+        }
+    }
+
+    pub fn series(&self, function: PrimeFunction, sampling: Sampling) -> Series {
+        sampling
+            .points()
+            .map(|x| (x, self.eval(function, x)))
+            .collect()
+    }
+}
+
+/// Psi rebuilt from the zeroes with the given heights,
+/// x - sum over rho of x^rho / rho - log(2 pi) - log(1 - x^-2) / 2.
+/// Below 2 there are no primes and it's 0, like the exact psi.
+pub fn psi(x: f64, heights: &[f64]) -> f64 {
+    if x < 2.0 {
+        return 0.0;
+    }
+    let ln_x = x.ln();
+    let sqrt_x = x.sqrt();
+    let oscillation: f64 = heights
+        .iter()
+        .map(|&g| {
+            let (s, c) = (g * ln_x).sin_cos();
+            // the zero and its conjugate together
+            2.0 * sqrt_x * (0.5 * c + g * s) / (0.25 + g * g)
+        })
+        .sum();
+    x - oscillation - (2.0 * PI).ln() - 0.5 * (1.0 - x.powi(-2)).ln()
+}
+
+/// Exponential integral Ei for real arguments, by its power series
+fn ei(y: f64) -> f64 {
+    let mut term = 1.0;
+    let mut sum = 0.0;
+    for k in 1..500 {
+        term *= y / k as f64;
+        let add = term / k as f64;
+        sum += add;
+        if add.abs() < 1e-17 * sum.abs() {
+            break;
+        }
+    }
+    EULER_GAMMA + y.abs().ln() + sum
+}
+
+/// Logarithmic integral li(x) = Ei(log x)
+pub fn li(x: f64) -> f64 {
+    ei(x.ln())
+}
+
+/// 2 Re li(x^rho) for rho = 1/2 + i gamma, which is the contribution
+/// of a zero and its conjugate. Uses the asymptotic series of Ei, which
+/// is accurate since |rho log x| is at least 14 log 2.
+fn li_pair(ln_x: f64, gamma: f64) -> f64 {
+    let (wr, wi) = (0.5 * ln_x, gamma * ln_x);
+    let norm = wr * wr + wi * wi;
+    // 1 / w
+    let (ir, ii) = (wr / norm, -wi / norm);
+
+    // sum of k! / w^k, stopping at the smallest term
+    let (mut tr, mut ti) = (1.0, 0.0);
+    let (mut sr, mut si) = (1.0, 0.0);
+    let mut last = 1.0;
+    for k in 1..100 {
+        let (nr, ni) = (tr * ir - ti * ii, tr * ii + ti * ir);
+        let (nr, ni) = (nr * k as f64, ni * k as f64);
+        let size = nr.hypot(ni);
+        if size > last || size < 1e-17 {
+            break;
+        }
+        (tr, ti, last) = (nr, ni, size);
+        sr += tr;
+        si += ti;
+    }
+
+    // e^w / w
+    let scale = wr.exp();
+    let (er, ei) = (scale * wi.cos(), scale * wi.sin());
+    let (qr, qi) = (er * ir - ei * ii, er * ii + ei * ir);
+    2.0 * (qr * sr - qi * si)
+}
+
+/// Riemann's prime power counting function J(x), rebuilt from the zeroes
+fn riemann_j(x: f64, heights: &[f64]) -> f64 {
+    if x < 2.0 {
+        return 0.0;
+    }
+    let ln_x = x.ln();
+    let oscillation: f64 = heights.iter().map(|&g| li_pair(ln_x, g)).sum();
+
+    // integral from x to infinity of dt / (t (t^2 - 1) log t), with
+    // t = e^u and Simpson's rule, the integrand has decayed by u = ln_x + 20
+    let steps = 200;
+    let h = 20.0 / steps as f64;
+    let f = |u: f64| 1.0 / (u * ((2.0 * u).exp() - 1.0));
+    let tail = (0..=steps)
+        .map(|i| {
+            let weight = match i {
+                0 => 1.0,
+                i if i == steps => 1.0,
+                i if i % 2 == 1 => 4.0,
+                _ => 2.0,
+            };
+            weight * f(ln_x + i as f64 * h)
+        })
+        .sum::<f64>()
+        * h
+        / 3.0;
+
+    li(x) - oscillation - LN_2 + tail
+}
+
+/// Mobius function by trial division
+fn mobius(mut n: usize) -> i32 {
+    let mut result = 1;
+    let mut p = 2;
+    while p * p <= n {
+        if n.is_multiple_of(p) {
+            n /= p;
+            if n.is_multiple_of(p) {
+                return 0;
+            }
+            result = -result;
+        }
+        p += 1;
+    }
+    if n > 1 {
+        -result
+    } else {
+        result
+    }
+}
+
+/// pi rebuilt from the zeroes with the given heights, by Mobius
+/// inversion of J(x)
+pub fn pi(x: f64, heights: &[f64]) -> f64 {
+    (1..)
+        .map(|n| (n, x.powf(1.0 / n as f64)))
+        .take_while(|&(_, root)| root >= 2.0)
+        .map(|(n, root)| mobius(n) as f64 / n as f64 * riemann_j(root, heights))
+        .sum()
+}
+
+pub fn eval(function: PrimeFunction, x: f64, heights: &[f64]) -> f64 {
+    match function {
+        PrimeFunction::Psi => psi(x, heights),
+        PrimeFunction::Pi => pi(x, heights),
+    }
+}
+
+/// The rebuilt function sampled over `sampling`
+pub fn series(function: PrimeFunction, heights: &[f64], sampling: Sampling) -> Series {
+    sampling
+        .points()
+        .map(|x| (x, eval(function, x, heights)))
+        .collect()
+}
+
+/// Root mean square error against the sieve over `sampling`, for each
+/// number of zeroes K in `zero_counts`. Gives a (K, error) series.
+pub fn error_series(
+    function: PrimeFunction,
+    heights: &[f64],
+    zero_counts: &[usize],
+    sampling: Sampling,
+) -> Series {
+    let sieve = PrimeSieve::new(sampling.end.ceil() as usize);
+    let exact: Vec<f64> = sampling.points().map(|x| sieve.eval(function, x)).collect();
+    zero_counts
+        .iter()
+        .map(|&k| {
+            let used = &heights[..k.min(heights.len())];
+            let squares: f64 = sampling
+                .points()
+                .zip(exact.iter())
+                .map(|(x, e)| (eval(function, x, used) - e).powi(2))
+                .sum();
+            (k as f64, (squares / sampling.samples as f64).sqrt())
+        })
+        .collect()
+}
+
+#[test]
+fn test_sieve() {
+    let sieve = PrimeSieve::new(100);
+    assert_eq!(sieve.pi(100.0), 25.0);
+    assert_eq!(sieve.pi(10.5), 4.0);
+    assert!(sieve.is_prime(97));
+    assert!(!sieve.is_prime(91));
+    // log(2^3 3^2 5 7)
+    assert!((sieve.psi(10.0) - 2520f64.ln()).abs() < 1e-9);
+}
+
+#[test]
+fn test_psi_below_two() {
+    let heights = [14.134_725, 21.022_04];
+    for x in [-1.0, 0.0, 0.5, 1.0, 1.5] {
+        assert_eq!(psi(x, &heights), 0.0, "{}", x);
+    }
+    assert!(psi(2.0, &heights).is_finite());
+    assert_eq!(Sampling::new(0.0, 1.0, 0).points().count(), 0);
+    assert_eq!(
+        Sampling::new(3.0, 5.0, 1).points().collect::<Vec<_>>(),
+        [3.0]
+    );
+}
+
+#[test]
+fn test_li() {
+    assert!((li(2.0) - 1.045_163_780_117_493).abs() < 1e-12);
+    assert!((li(1000.0) - 177.609_657_990_152_2).abs() < 1e-9);
+}
+
+#[test]
+fn test_explicit_formula_near_exact() {
+    let heights = crate::Zeroes::load().heights();
+    let sieve = PrimeSieve::new(200);
+    // between primes, away from the jumps
+    for x in [50.5, 100.5, 150.5] {
+        assert!((psi(x, &heights) - sieve.psi(x)).abs() < 0.5);
+        assert!((pi(x, &heights) - sieve.pi(x)).abs() < 0.5);
+    }
+}
+
+#[test]
+fn test_error_shrinks_with_more_zeroes() {
+    let heights = crate::Zeroes::load().heights();
+    let sampling = Sampling::new(20.5, 60.5, 41);
+    for function in [PrimeFunction::Psi, PrimeFunction::Pi] {
+        let errors = error_series(function, &heights, &[10, 100, 1000], sampling);
+        assert!(errors[0].1 > errors[1].1);
+        assert!(errors[1].1 > errors[2].1);
+    }
+}
@@ -1,11 +1,15 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    num::ParseIntError,
-    str::FromStr,
-};
+use std::{ops::RangeBounds, slice};
 
 use bigdecimal::{BigDecimal, ToPrimitive};
+use loader::Format;
+
+pub mod cache;
+pub mod explicit_formula;
+pub mod loader;
+pub mod riemann_siegel;
+pub mod sonify;
+pub mod stats;
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Zero {
     pos: u64,
@@ -17,77 +21,194 @@ impl Zero {
         Self { pos, bigd }
     }
 
-    fn int_val(&self) -> i64 {
-        self.bigd.to_i64().unwrap()
+    /// Ordinal of the zero, the first zero above the real axis is 1
+    pub fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    /// Height of the zero on the critical line at full precision
+    pub fn value(&self) -> &BigDecimal {
+        &self.bigd
+    }
+
+    /// Whole part of the height, exact however high the zero is
+    pub fn int_part(&self) -> BigDecimal {
+        self.bigd.with_scale(0)
+    }
+
+    /// Whole part of the height, None past the range of i128
+    pub fn int_val(&self) -> Option<i128> {
+        self.int_part().into_bigint_and_exponent().0.to_i128()
     }
 
-    fn float_val(&self) -> f64 {
+    pub fn float_val(&self) -> f64 {
         self.bigd.to_f64().unwrap()
     }
 
-    fn fract(&self) -> BigDecimal {
-        self.bigd.clone() - BigDecimal::from(self.int_val())
+    pub fn fract(&self) -> BigDecimal {
+        &self.bigd - self.int_part()
     }
 
-    fn fract_float(&self) -> f32 {
-        self.fract().to_f32().unwrap()
+    pub fn fract_float(&self) -> f32 {
+        self.fract().to_f32().expect("a fraction fits an f32")
     }
 }
 
+/// The loaded zeroes, queryable by ordinal or height and iterable
+/// from either end.
+///
+/// Iterating consumes from the front and back cursors, the query
+/// methods always look at the full data set.
 pub struct Zeroes {
     track_pos: usize,
+    back_pos: usize,
     zeroes: Vec<Zero>,
 }
 
-fn parse_line(line: String) -> Result<Zero, ParseIntError> {
-    let mut split = line.split(' ');
-    let pos: u64 = split.next().unwrap().parse::<u64>()?;
-    let part2 = split.next().unwrap();
-    let dec = BigDecimal::from_str(part2);
-    Ok(Zero::new(pos, dec.unwrap()))
-}
-
-fn read_zeroes() -> Vec<Zero> {
-    let file = File::open("zeros10000.txt").expect("file at zeroes 10000 txt");
-
-    let buf_reader = BufReader::new(file);
-    let mut zeroes = Vec::new();
-    for line in buf_reader.lines().map(|l| l.unwrap()) {
-        let pair = parse_line(line).expect("no parse errors");
-        zeroes.push(pair);
-    }
-    zeroes
-}
-
 impl Zeroes {
+    /// Load the bundled `zeros10000.txt`
     pub fn load() -> Self {
+        Self::load_from("zeros10000.txt", Format::Indexed).expect("file at zeroes 10000 txt")
+    }
+
+    fn from_vec(zeroes: Vec<Zero>) -> Self {
         Self {
             track_pos: 0,
-            zeroes: read_zeroes(),
+            back_pos: zeroes.len(),
+            zeroes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.zeroes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zeroes.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[Zero] {
+        &self.zeroes
+    }
+
+    /// Sub slice by index into the data set (0 based, unlike `pos`)
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> &[Zero] {
+        &self.zeroes[(range.start_bound().cloned(), range.end_bound().cloned())]
+    }
+
+    /// Heights of all the zeroes as floats, for the statistics
+    pub fn heights(&self) -> Vec<f64> {
+        self.zeroes.iter().map(Zero::float_val).collect()
+    }
+
+    /// Iterate over all the zeroes without moving the cursors
+    pub fn iter(&self) -> slice::Iter<'_, Zero> {
+        self.zeroes.iter()
+    }
+
+    /// Zero with ordinal `pos`, counting from 1
+    pub fn get(&self, pos: u64) -> Option<&Zero> {
+        self.zeroes
+            .binary_search_by_key(&pos, |z| z.pos)
+            .ok()
+            .map(|i| &self.zeroes[i])
+    }
+
+    /// Zeroes with height in the closed interval [t1, t2]
+    pub fn between(&self, t1: f64, t2: f64) -> &[Zero] {
+        if t2 < t1 {
+            return &[];
         }
+        let start = self.zeroes.partition_point(|z| z.float_val() < t1);
+        let end = self.zeroes.partition_point(|z| z.float_val() <= t2);
+        &self.zeroes[start..end]
+    }
+
+    /// N(T), the number of zeroes with height at most `t`
+    pub fn count_up_to(&self, t: f64) -> usize {
+        self.zeroes.partition_point(|z| z.float_val() <= t)
     }
 }
+
 impl Iterator for Zeroes {
     type Item = Zero;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.track_pos += 1;
+        if self.track_pos < self.back_pos {
+            let zero = self.zeroes[self.track_pos].clone();
+            self.track_pos += 1;
+            Some(zero)
+        } else {
+            None
+        }
+    }
 
-        if self.track_pos < self.zeroes.len() {
-            Some(self.zeroes[self.track_pos].clone())
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.back_pos - self.track_pos;
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for Zeroes {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.track_pos < self.back_pos {
+            self.back_pos -= 1;
+            Some(self.zeroes[self.back_pos].clone())
         } else {
             None
         }
     }
 }
 
-#[test]
-fn test_first_zero() {
+impl<'a> IntoIterator for &'a Zeroes {
+    type Item = &'a Zero;
+    type IntoIter = slice::Iter<'a, Zero>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.zeroes.iter()
+    }
+}
+
+#[cfg(test)]
+fn first_zero() -> Zero {
     use num_bigint::BigInt;
-    let zeroes = Zeroes::load();
-    let first_zero = Zero::new(
+    Zero::new(
         1,
         BigDecimal::new(BigInt::from(141347251417346937904572519835625_i128), 31),
-    );
-    assert_eq!(first_zero, *zeroes.zeroes.first().unwrap());
+    )
+}
+
+#[test]
+fn test_first_zero() {
+    let zeroes = Zeroes::load();
+    assert_eq!(first_zero(), *zeroes.zeroes.first().unwrap());
+}
+
+#[test]
+fn test_iter_includes_first_zero() {
+    let mut zeroes = Zeroes::load();
+    assert_eq!(zeroes.len(), 10000);
+    assert_eq!(zeroes.next().unwrap().pos(), 1);
+    assert_eq!(zeroes.next_back().unwrap().pos(), 10000);
+    assert_eq!(zeroes.count(), 9998);
+}
+
+#[test]
+fn test_queries() {
+    let zeroes = Zeroes::load();
+    assert_eq!(zeroes.get(1).unwrap().int_val(), Some(14));
+    assert_eq!(zeroes.get(3).unwrap().int_val(), Some(25));
+    assert!(zeroes.get(0).is_none());
+    assert!(zeroes.get(10001).is_none());
+
+    let window = zeroes.between(20.0, 30.0);
+    assert_eq!(window.iter().map(Zero::pos).collect::<Vec<_>>(), vec![2, 3]);
+    assert!(zeroes.between(30.0, 20.0).is_empty());
+
+    assert_eq!(zeroes.count_up_to(14.0), 0);
+    assert_eq!(zeroes.count_up_to(14.2), 1);
+    assert_eq!(zeroes.count_up_to(100.0), 29);
+    assert_eq!(zeroes.count_up_to(1e6), 10000);
+    assert_eq!(zeroes.slice(..2).len(), 2);
+    assert_eq!(zeroes.slice(9990..).len(), 10);
 }
@@ -0,0 +1,206 @@
+//! Reading zero tables from text files, in memory or as a stream
+//!
+//! Besides the "index value" lines of `zeros10000.txt` this understands
+//! the tables published by Andrew Odlyzko, which list one value per line,
+//! and his high tables, which list values with a large offset subtracted
+//! and start part way through the zeroes.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Lines},
+    path::Path,
+    str::FromStr,
+};
+
+use bigdecimal::BigDecimal;
+
+use crate::{Zero, Zeroes};
+
+/// Layout of the lines in a zero table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// "index value" on each line, like `zeros10000.txt`
+    Indexed,
+    /// One value per line, numbered from `first_pos`
+    Plain { first_pos: u64 },
+    /// One value per line with `offset` subtracted, numbered from
+    /// `first_pos`. The high tables state both in their text header,
+    /// which is skipped rather than parsed, so the caller supplies them.
+    Offset { first_pos: u64, offset: BigDecimal },
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse { line: usize, text: String },
+    Cache(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "io error: {}", e),
+            LoadError::Parse { line, text } => write!(f, "can't parse line {}: {:?}", line, text),
+            LoadError::Cache(e) => write!(f, "bad cache: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// Streams zeroes out of a table one line at a time, so files of any size
+/// can be scanned in constant memory.
+///
+/// Blank lines and lines that don't start with a digit, like the text
+/// headers of the high tables, are skipped.
+pub struct ZeroReader<R> {
+    lines: Lines<R>,
+    format: Format,
+    line: usize,
+    next_pos: u64,
+}
+
+impl<R: BufRead> ZeroReader<R> {
+    pub fn new(reader: R, format: Format) -> Self {
+        let next_pos = match &format {
+            Format::Indexed => 1,
+            Format::Plain { first_pos } | Format::Offset { first_pos, .. } => *first_pos,
+        };
+        Self {
+            lines: reader.lines(),
+            format,
+            line: 0,
+            next_pos,
+        }
+    }
+
+    fn parse(&mut self, text: &str) -> Option<Zero> {
+        let mut split = text.split_whitespace();
+        let zero = match &self.format {
+            Format::Indexed => {
+                let pos = split.next()?.parse().ok()?;
+                let value = BigDecimal::from_str(split.next()?).ok()?;
+                Zero::new(pos, value)
+            }
+            Format::Plain { .. } => {
+                Zero::new(self.next_pos, BigDecimal::from_str(split.next()?).ok()?)
+            }
+            Format::Offset { offset, .. } => Zero::new(
+                self.next_pos,
+                offset + BigDecimal::from_str(split.next()?).ok()?,
+            ),
+        };
+        self.next_pos = zero.pos + 1;
+        Some(zero)
+    }
+}
+
+impl ZeroReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P, format: Format) -> Result<Self, LoadError> {
+        Ok(Self::new(BufReader::new(File::open(path)?), format))
+    }
+}
+
+impl<R: BufRead> Iterator for ZeroReader<R> {
+    type Item = Result<Zero, LoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.lines.next()? {
+                Ok(text) => text,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+            let trimmed = text.trim();
+            if !trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+                continue;
+            }
+            return Some(self.parse(trimmed).ok_or(LoadError::Parse {
+                line: self.line,
+                text,
+            }));
+        }
+    }
+}
+
+impl Zeroes {
+    /// Load a whole table into memory
+    pub fn load_from<P: AsRef<Path>>(path: P, format: Format) -> Result<Self, LoadError> {
+        let zeroes = ZeroReader::open(path, format)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_vec(zeroes))
+    }
+
+    /// Stream a table without holding it in memory
+    pub fn stream<P: AsRef<Path>>(
+        path: P,
+        format: Format,
+    ) -> Result<ZeroReader<BufReader<File>>, LoadError> {
+        ZeroReader::open(path, format)
+    }
+}
+
+#[test]
+fn test_plain_format() {
+    let text = "14.134725142\n21.022039639\n\n25.010857580\n";
+    let zeroes: Vec<Zero> = ZeroReader::new(text.as_bytes(), Format::Plain { first_pos: 1 })
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(zeroes.len(), 3);
+    assert_eq!(zeroes[2].pos(), 3);
+    assert_eq!(zeroes[1].int_val(), Some(21));
+}
+
+#[test]
+fn test_offset_format_skips_header() {
+    let text = "The imaginary parts of zeros 10^12+1 through 10^12+3,\n\
+                with 267653395647 subtracted from each\n\
+                0.20180528\n0.39869513\n0.71054553\n";
+    let format = Format::Offset {
+        first_pos: 1_000_000_000_001,
+        offset: BigDecimal::from(267_653_395_647_u64),
+    };
+    let zeroes: Vec<Zero> = ZeroReader::new(text.as_bytes(), format)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(zeroes.len(), 3);
+    assert_eq!(zeroes[0].pos(), 1_000_000_000_001);
+    assert_eq!(zeroes[2].pos(), 1_000_000_000_003);
+    assert_eq!(zeroes[1].int_val(), Some(267_653_395_647));
+    assert!((zeroes[1].fract_float() - 0.398_695_13).abs() < 1e-6);
+}
+
+#[test]
+fn test_heights_past_i64() {
+    // high tables reach heights around 10^20, which don't fit an i64
+    let text = "header\n0.17234939\n";
+    let format = Format::Offset {
+        first_pos: 1,
+        offset: "144176897509546973000".parse().unwrap(),
+    };
+    let zeroes: Vec<Zero> = ZeroReader::new(text.as_bytes(), format)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(zeroes[0].int_val(), Some(144_176_897_509_546_973_000));
+    assert!((zeroes[0].fract_float() - 0.172_349_4).abs() < 1e-6);
+}
+
+#[test]
+fn test_parse_error_reports_line() {
+    let text = "1 14.13\n2 abc\n";
+    let result: Result<Vec<Zero>, _> = ZeroReader::new(text.as_bytes(), Format::Indexed).collect();
+    assert!(matches!(result, Err(LoadError::Parse { line: 2, .. })));
+}
+
+#[test]
+fn test_stream_matches_load() {
+    let loaded = Zeroes::load();
+    let streamed = Zeroes::stream("zeros10000.txt", Format::Indexed).unwrap();
+    assert!(streamed.map(Result::unwrap).eq(loaded.iter().cloned()));
+}
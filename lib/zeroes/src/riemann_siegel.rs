@@ -0,0 +1,435 @@
+//! Zeroes of the zeta function computed from the Riemann-Siegel Z function
+//!
+//! Z(t) is real on the critical line and has the same zeroes as
+//! zeta(1/2 + it), so zeroes are found by looking for sign changes between
+//! Gram points and refining them with a bracketing root finder.
+//!
+//! The asymptotic expansion is rough for the first few zeroes, which are
+//! only good to about 1e-5, but by height 1000 the error is around 1e-11.
+//! Everything is computed in `f64`, so asking for a precision finer than
+//! that only costs time.
+
+use std::{
+    f64::consts::{PI, TAU},
+    fmt,
+    sync::OnceLock,
+};
+
+use bigdecimal::{BigDecimal, FromPrimitive};
+
+use crate::{Zero, Zeroes};
+
+/// Number of Taylor coefficients kept for the remainder function
+const TAYLOR_TERMS: usize = 64;
+
+/// Radius of the circle the Taylor coefficients are sampled on
+const TAYLOR_RADIUS: f64 = 2.0;
+
+/// Highest derivative of psi needed by the remainder terms
+const MAX_DERIVATIVE: usize = 12;
+
+/// Most root finding steps spent on a single zero, for when the requested
+/// precision is finer than f64 can resolve
+const MAX_REFINE_STEPS: usize = 200;
+
+/// Most times a Gram block is subdivided looking for missing sign changes
+const MAX_REFINE: u32 = 12;
+
+/// A Gram block whose sign changes didn't match the number of zeroes it
+/// should hold, even after subdividing it `MAX_REFINE` times. Numbering
+/// what was found would put every later ordinal in the block out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CountError {
+    /// Indices of the good Gram points at the ends of the block
+    pub block: (i64, i64),
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "found {} sign changes between Gram points {} and {}, expected {}",
+            self.found, self.block.0, self.block.1, self.expected
+        )
+    }
+}
+
+impl std::error::Error for CountError {}
+
+/// Riemann-Siegel theta function, via its asymptotic expansion.
+/// Accurate for t larger than about 10.
+pub fn theta(t: f64) -> f64 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let t5 = t3 * t2;
+    let t7 = t5 * t2;
+    0.5 * t * (t / TAU).ln() - 0.5 * t - PI / 8.0
+        + 1.0 / (48.0 * t)
+        + 7.0 / (5760.0 * t3)
+        + 31.0 / (80640.0 * t5)
+        + 127.0 / (430080.0 * t7)
+}
+
+/// Derivative of the leading terms of theta, good enough for Newton steps
+fn theta_prime(t: f64) -> f64 {
+    0.5 * (t / TAU).ln()
+}
+
+/// Closed form of the first remainder coefficient,
+/// cos(2pi(p^2 - p - 1/16)) / cos(2pi p)
+#[cfg(test)]
+fn psi(p: f64) -> f64 {
+    (TAU * (p * p - p - 1.0 / 16.0)).cos() / (TAU * p).cos()
+}
+
+/// psi written in terms of z = 2p - 1 for complex z, given and returned
+/// as (re, im) pairs: -cos(pi z^2 / 2 - 5 pi / 8) / cos(pi z)
+fn psi_complex(re: f64, im: f64) -> (f64, f64) {
+    let cos = |x: f64, y: f64| (x.cos() * y.cosh(), -x.sin() * y.sinh());
+    let (nr, ni) = cos(
+        0.5 * PI * (re * re - im * im) - 5.0 * PI / 8.0,
+        PI * re * im,
+    );
+    let (dr, di) = cos(PI * re, PI * im);
+    let denom = dr * dr + di * di;
+    (-(nr * dr + ni * di) / denom, -(ni * dr - nr * di) / denom)
+}
+
+/// Taylor series around p = 1/2 of psi and its derivatives, in powers of
+/// z = 2p - 1. Index k holds the k-th derivative with respect to p.
+///
+/// psi has removable singularities at p = 1/4 and p = 3/4, so evaluating
+/// it and differentiating numerically loses most of the precision. It is
+/// entire though, so the Taylor coefficients come cleanly from a Cauchy
+/// integral around a circle in the complex plane.
+fn psi_series() -> &'static [Vec<f64>; MAX_DERIVATIVE + 1] {
+    static SERIES: OnceLock<[Vec<f64>; MAX_DERIVATIVE + 1]> = OnceLock::new();
+    SERIES.get_or_init(|| {
+        let m = TAYLOR_TERMS;
+        let samples: Vec<(f64, f64)> = (0..m)
+            .map(|k| {
+                let angle = TAU * k as f64 / m as f64;
+                psi_complex(TAYLOR_RADIUS * angle.cos(), TAYLOR_RADIUS * angle.sin())
+            })
+            .collect();
+        let coeffs: Vec<f64> = (0..m)
+            .map(|n| {
+                let sum: f64 = samples
+                    .iter()
+                    .enumerate()
+                    .map(|(k, (re, im))| {
+                        let angle = TAU * (n * k) as f64 / m as f64;
+                        re * angle.cos() + im * angle.sin()
+                    })
+                    .sum();
+                sum / (m as f64 * TAYLOR_RADIUS.powi(n as i32))
+            })
+            .collect();
+
+        let mut series: [Vec<f64>; MAX_DERIVATIVE + 1] = Default::default();
+        series[0] = coeffs;
+        for k in 1..=MAX_DERIVATIVE {
+            // d/dp = 2 d/dz
+            series[k] = series[k - 1]
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(n, c)| 2.0 * n as f64 * c)
+                .collect();
+        }
+        series
+    })
+}
+
+/// k-th derivative of psi at p
+fn psi_derivative(k: usize, p: f64) -> f64 {
+    let z = 2.0 * p - 1.0;
+    psi_series()[k].iter().rev().fold(0.0, |acc, c| acc * z + c)
+}
+
+/// The Riemann-Siegel Z function, Z(t) = exp(i theta(t)) zeta(1/2 + it).
+///
+/// Uses the main sum plus the first five terms of the remainder, so the
+/// error shrinks like t^(-11/4).
+pub fn z(t: f64) -> f64 {
+    let tau = (t / TAU).sqrt();
+    let n = tau.floor() as usize;
+    let p = tau - n as f64;
+    let th = theta(t);
+
+    let main: f64 = (1..=n)
+        .map(|k| {
+            let k = k as f64;
+            (th - t * k.ln()).cos() / k.sqrt()
+        })
+        .sum();
+
+    let d = |k| psi_derivative(k, p);
+    let pi2 = PI * PI;
+    let pi4 = pi2 * pi2;
+    let pi6 = pi4 * pi2;
+    let c0 = d(0);
+    let c1 = -d(3) / (96.0 * pi2);
+    let c2 = d(2) / (64.0 * pi2) + d(6) / (18432.0 * pi4);
+    let c3 = -d(1) / (64.0 * pi2) - d(5) / (3840.0 * pi4) - d(9) / (5308416.0 * pi6);
+    let c4 = d(0) / (128.0 * pi2)
+        + 19.0 * d(4) / (24576.0 * pi4)
+        + 11.0 * d(8) / (5898240.0 * pi6)
+        + d(12) / (2038431744.0 * pi6 * pi2);
+
+    let a = tau.recip();
+    let sign = if n % 2 == 1 { 1.0 } else { -1.0 };
+    let remainder = sign * a.sqrt() * (c0 + a * (c1 + a * (c2 + a * (c3 + a * c4))));
+
+    2.0 * main + remainder
+}
+
+/// The n-th Gram point, where theta(g_n) = n pi. n may be -1, giving the
+/// Gram point below the first zero.
+pub fn gram_point(n: i64) -> f64 {
+    let target = n as f64 * PI;
+    // theta is convex, so Newton from above the root converges monotonically
+    let mut t = 20.0;
+    while theta(t) < target {
+        t *= 2.0;
+    }
+    for _ in 0..100 {
+        let step = (theta(t) - target) / theta_prime(t);
+        t -= step;
+        if step.abs() < 1e-12 * t {
+            break;
+        }
+    }
+    t
+}
+
+/// A Gram point is good when Z has the sign Gram's law predicts there
+fn is_good_gram(n: i64) -> bool {
+    let value = z(gram_point(n));
+    if n % 2 == 0 {
+        value > 0.0
+    } else {
+        value < 0.0
+    }
+}
+
+/// Refine a sign change of Z in [a, b] with the Illinois variant of
+/// regula falsi until the bracket is narrower than `precision`.
+fn refine(mut a: f64, mut b: f64, precision: f64) -> f64 {
+    let mut fa = z(a);
+    let mut fb = z(b);
+    let mut side = 0;
+    for _ in 0..MAX_REFINE_STEPS {
+        if (b - a).abs() <= precision {
+            break;
+        }
+        let c = (a * fb - b * fa) / (fb - fa);
+        // fall back to bisection if the secant lands on an end point
+        let c = if c <= a || c >= b { 0.5 * (a + b) } else { c };
+        let fc = z(c);
+        if fc == 0.0 {
+            return c;
+        }
+        if fc.signum() == fb.signum() {
+            b = c;
+            fb = fc;
+            if side == -1 {
+                fa *= 0.5;
+            }
+            side = -1;
+        } else {
+            a = c;
+            fa = fc;
+            if side == 1 {
+                fb *= 0.5;
+            }
+            side = 1;
+        }
+    }
+    0.5 * (a + b)
+}
+
+/// Sign changes of Z in the Gram block between the good Gram points
+/// `start` and `end`, which should contain `end - start` zeroes.
+/// The block is subdivided further until all are found, and it's an
+/// error if they can't all be found or if there are more sign changes
+/// than zeroes.
+fn block_zeroes(start: i64, end: i64, precision: f64) -> Result<Vec<f64>, CountError> {
+    let expected = (end - start) as usize;
+    let grams: Vec<f64> = (start..=end).map(gram_point).collect();
+
+    let mut brackets = Vec::new();
+    for refinement in 0..MAX_REFINE {
+        let steps = 1 << refinement;
+        let mut points = Vec::with_capacity(expected * steps + 1);
+        for pair in grams.windows(2) {
+            let width = pair[1] - pair[0];
+            (0..steps).for_each(|s| points.push(pair[0] + width * s as f64 / steps as f64));
+        }
+        points.push(grams[grams.len() - 1]);
+
+        let values: Vec<f64> = points.iter().map(|&t| z(t)).collect();
+        brackets = points
+            .windows(2)
+            .zip(values.windows(2))
+            .filter(|(_, v)| v[0].signum() != v[1].signum())
+            .map(|(t, _)| (t[0], t[1]))
+            .collect();
+        // finer sampling can only find more sign changes, never fewer
+        if brackets.len() >= expected {
+            break;
+        }
+    }
+    if brackets.len() != expected {
+        return Err(CountError {
+            block: (start, end),
+            expected,
+            found: brackets.len(),
+        });
+    }
+    Ok(brackets
+        .into_iter()
+        .map(|(a, b)| refine(a, b, precision))
+        .collect())
+}
+
+/// Nearest good Gram point index at or below `n`
+fn good_gram_below(mut n: i64) -> i64 {
+    while n > -1 && !is_good_gram(n) {
+        n -= 1;
+    }
+    n.max(-1)
+}
+
+/// Nearest good Gram point index at or above `n`
+fn good_gram_above(mut n: i64) -> i64 {
+    while !is_good_gram(n) {
+        n += 1;
+    }
+    n
+}
+
+/// Zeroes found between two good Gram points, paired with their ordinals.
+/// There are n + 1 zeroes below the good Gram point g_n.
+fn zeroes_between_grams(
+    start: i64,
+    end: i64,
+    precision: f64,
+) -> Result<Vec<(u64, f64)>, CountError> {
+    let mut found = Vec::new();
+    let mut block_start = start;
+    while block_start < end {
+        let block_end = good_gram_above(block_start + 1);
+        let first_pos = (block_start + 2) as u64;
+        block_zeroes(block_start, block_end, precision)?
+            .into_iter()
+            .enumerate()
+            .for_each(|(i, t)| found.push((first_pos + i as u64, t)));
+        block_start = block_end;
+    }
+    Ok(found)
+}
+
+fn to_zero(pos: u64, t: f64) -> Zero {
+    Zero::new(pos, BigDecimal::from_f64(t).expect("finite zero height"))
+}
+
+impl Zeroes {
+    /// Compute the first `n` zeroes, refined until the bracket around each
+    /// is narrower than `precision`. Fails rather than misnumber zeroes if
+    /// a Gram block's zeroes can't be separated.
+    pub fn compute_first(n: usize, precision: f64) -> Result<Self, CountError> {
+        let mut found = Vec::with_capacity(n);
+        let mut start = -1;
+        while found.len() < n {
+            // blocks of roughly a hundred Gram intervals at a time
+            let end = good_gram_above(start + 100);
+            found.extend(zeroes_between_grams(start, end, precision)?);
+            start = end;
+        }
+        found.truncate(n);
+        Ok(Self::from_vec(
+            found.into_iter().map(|(p, t)| to_zero(p, t)).collect(),
+        ))
+    }
+
+    /// Compute the zeroes with height in [t1, t2], refined until the bracket
+    /// around each is narrower than `precision`, failing like
+    /// `compute_first`
+    pub fn compute_between(t1: f64, t2: f64, precision: f64) -> Result<Self, CountError> {
+        if t2 < t1 {
+            return Ok(Self::from_vec(Vec::new()));
+        }
+        let start = good_gram_below((theta(t1.max(10.0)) / PI).floor() as i64);
+        let end = good_gram_above((theta(t2.max(10.0)) / PI).ceil() as i64);
+        let zeroes = zeroes_between_grams(start, end, precision)?
+            .into_iter()
+            .filter(|&(_, t)| t >= t1 && t <= t2)
+            .map(|(p, t)| to_zero(p, t))
+            .collect();
+        Ok(Self::from_vec(zeroes))
+    }
+}
+
+#[test]
+fn test_psi_series_matches_closed_form() {
+    for p in [0.0, 0.1, 0.4, 0.5, 0.6, 0.9, 1.0] {
+        assert!((psi_derivative(0, p) - psi(p)).abs() < 1e-13);
+        let h = 1e-5;
+        let slope = (psi(p + h) - psi(p - h)) / (2.0 * h);
+        assert!((psi_derivative(1, p) - slope).abs() < 1e-6);
+    }
+    // removable singularity at 1/4 is finite
+    assert!(psi_derivative(0, 0.25).is_finite());
+}
+
+#[test]
+fn test_theta_at_gram_points() {
+    assert!((gram_point(0) - 17.845_599_540_6).abs() < 1e-6);
+    assert!((theta(gram_point(10)) - 10.0 * PI).abs() < 1e-9);
+}
+
+#[test]
+fn test_computed_first_zero() {
+    let zeroes = Zeroes::compute_first(1, 1e-12).unwrap();
+    let first = zeroes.get(1).unwrap();
+    let expected = crate::first_zero().float_val();
+    assert!((first.float_val() - expected).abs() < 1e-5);
+}
+
+#[test]
+fn test_computed_match_dataset() {
+    let dataset = Zeroes::load();
+    let computed = Zeroes::compute_first(1000, 1e-10).unwrap();
+    assert_eq!(computed.len(), 1000);
+    for zero in computed.iter() {
+        let known = dataset.get(zero.pos()).unwrap();
+        assert!((zero.float_val() - known.float_val()).abs() < 1e-5);
+    }
+
+    let window = Zeroes::compute_between(7000.0, 7010.0, 1e-10).unwrap();
+    let known = dataset.between(7000.0, 7010.0);
+    assert_eq!(window.len(), known.len());
+    for (zero, known) in window.iter().zip(known) {
+        assert_eq!(zero.pos(), known.pos());
+        assert!((zero.float_val() - known.float_val()).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_block_counts_are_checked() {
+    // the first block, good Gram points -1 and 0, holds one zero
+    assert_eq!(block_zeroes(-1, 0, 1e-8).unwrap().len(), 1);
+    // Gram's law first fails between g_125 and g_126, which hold no zero,
+    // so taking them as a block fails instead of numbering the wrong zeroes
+    assert_eq!(
+        block_zeroes(125, 126, 1e-8),
+        Err(CountError {
+            block: (125, 126),
+            expected: 1,
+            found: 0,
+        })
+    );
+}
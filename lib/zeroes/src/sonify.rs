@@ -0,0 +1,262 @@
+//! Turning zeroes into sound
+//!
+//! A value is taken from each zero, its height, fractional part or the
+//! normalised spacing to the next zero, and mapped onto a pitch range.
+//! The mapping decides whether those pitches become a melody, a rhythm
+//! or the partials of one slowly building tone.
+
+use std::{f32::consts::TAU, path::Path, str::FromStr};
+
+use crate::{stats::normalised_spacings, Zero};
+
+/// Length of the linear fade in and out of each note, in seconds,
+/// to stop the notes clicking
+const FADE_SECS: f32 = 0.01;
+
+/// Peak amplitude of the rendered audio
+const PEAK: f32 = 0.9;
+
+/// Which value of each zero is heard
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Height,
+    Fract,
+    /// Normalised spacing to the next zero, so one fewer value than zeroes
+    Spacing,
+}
+
+impl Source {
+    pub fn values(&self, zeroes: &[Zero]) -> Vec<f32> {
+        match self {
+            Source::Height => zeroes.iter().map(|z| z.float_val() as f32).collect(),
+            Source::Fract => zeroes.iter().map(Zero::fract_float).collect(),
+            Source::Spacing => {
+                let heights: Vec<f64> = zeroes.iter().map(Zero::float_val).collect();
+                normalised_spacings(&heights)
+                    .into_iter()
+                    .map(|s| s as f32)
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "height" => Ok(Source::Height),
+            "fract" => Ok(Source::Fract),
+            "spacing" => Ok(Source::Spacing),
+            other => Err(format!(
+                "unknown source {}, expected height, fract or spacing",
+                other
+            )),
+        }
+    }
+}
+
+/// How the values become sound
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mapping {
+    /// One note a beat, the value sets the pitch
+    Pitch,
+    /// Notes at the lowest pitch, the value sets how many beats they last
+    Duration,
+    /// Additive synthesis, each value adds a partial on its own beat
+    /// which then sounds until the end
+    Partials,
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pitch" => Ok(Mapping::Pitch),
+            "duration" => Ok(Mapping::Duration),
+            "partials" => Ok(Mapping::Partials),
+            other => Err(format!(
+                "unknown mapping {}, expected pitch, duration or partials",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sonification {
+    pub source: Source,
+    pub mapping: Mapping,
+    pub sample_rate: u32,
+    /// Beats per minute
+    pub tempo: f32,
+    pub low_hz: f32,
+    pub high_hz: f32,
+}
+
+impl Default for Sonification {
+    fn default() -> Self {
+        Self {
+            source: Source::Fract,
+            mapping: Mapping::Pitch,
+            sample_rate: 44_100,
+            tempo: 240.0,
+            low_hz: 110.0,
+            high_hz: 880.0,
+        }
+    }
+}
+
+/// Rescale values to [0, 1]
+fn normalise(values: &[f32]) -> Vec<f32> {
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+    values
+        .iter()
+        .map(|v| if range > 0.0 { (v - min) / range } else { 0.5 })
+        .collect()
+}
+
+/// Add a sine note with faded ends into `out`, starting at sample `start`
+fn add_note(out: &mut [f32], start: usize, len: usize, freq: f32, amp: f32, sample_rate: f32) {
+    let fade = ((FADE_SECS * sample_rate) as usize).min(len / 2).max(1);
+    for i in 0..len.min(out.len().saturating_sub(start)) {
+        let envelope = (i.min(len - 1 - i) as f32 / fade as f32).min(1.0);
+        // cycles counted in f64 so long notes stay in tune
+        let cycles = (freq as f64 * i as f64 / sample_rate as f64).fract() as f32;
+        out[start + i] += amp * envelope * (TAU * cycles).sin();
+    }
+}
+
+impl Sonification {
+    /// Pitch for a normalised value, evenly spaced in octaves
+    pub fn frequency(&self, norm: f32) -> f32 {
+        self.low_hz * (self.high_hz / self.low_hz).powf(norm)
+    }
+
+    pub fn beat_samples(&self) -> usize {
+        (60.0 / self.tempo * self.sample_rate as f32) as usize
+    }
+
+    /// Mono samples in [-1, 1]
+    pub fn render(&self, zeroes: &[Zero]) -> Vec<f32> {
+        let norms = normalise(&self.source.values(zeroes));
+        let beat = self.beat_samples();
+        let rate = self.sample_rate as f32;
+
+        let mut out = match self.mapping {
+            Mapping::Pitch => {
+                let mut out = vec![0.0; beat * norms.len()];
+                for (i, &n) in norms.iter().enumerate() {
+                    add_note(&mut out, i * beat, beat, self.frequency(n), 1.0, rate);
+                }
+                out
+            }
+            Mapping::Duration => {
+                // a quarter beat to two beats
+                let lengths: Vec<usize> = norms
+                    .iter()
+                    .map(|n| ((0.25 + 1.75 * n) * beat as f32) as usize)
+                    .collect();
+                let mut out = vec![0.0; lengths.iter().sum()];
+                let mut start = 0;
+                for len in lengths {
+                    add_note(&mut out, start, len, self.low_hz, 1.0, rate);
+                    start += len;
+                }
+                out
+            }
+            Mapping::Partials => {
+                let total = beat * (norms.len() + 1);
+                let mut out = vec![0.0; total];
+                for (i, &n) in norms.iter().enumerate() {
+                    let start = i * beat;
+                    let amp = (i as f32 + 1.0).recip();
+                    add_note(&mut out, start, total - start, self.frequency(n), amp, rate);
+                }
+                out
+            }
+        };
+
+        let peak = out.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        if peak > 0.0 {
+            out.iter_mut().for_each(|s| *s *= PEAK / peak);
+        }
+        out
+    }
+
+    /// Render and write a 16 bit mono WAV file
+    pub fn write_wav<P: AsRef<Path>>(&self, zeroes: &[Zero], path: P) -> Result<(), hound::Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for sample in self.render(zeroes) {
+            writer.write_sample((sample * i16::MAX as f32) as i16)?;
+        }
+        writer.finalize()
+    }
+}
+
+#[test]
+fn test_render_lengths() {
+    let zeroes = crate::Zeroes::load();
+    let some = zeroes.slice(..10);
+    let sonification = Sonification {
+        sample_rate: 8000,
+        tempo: 120.0,
+        ..Default::default()
+    };
+    assert_eq!(sonification.beat_samples(), 4000);
+    assert_eq!(sonification.render(some).len(), 40000);
+
+    let partials = Sonification {
+        mapping: Mapping::Partials,
+        ..sonification
+    };
+    assert_eq!(partials.render(some).len(), 44000);
+
+    let spacing = Sonification {
+        source: Source::Spacing,
+        ..sonification
+    };
+    assert_eq!(spacing.render(some).len(), 36000);
+
+    let samples = Sonification {
+        mapping: Mapping::Duration,
+        ..sonification
+    }
+    .render(some);
+    let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    assert!((peak - PEAK).abs() < 1e-6);
+}
+
+#[test]
+fn test_frequency_range() {
+    let sonification = Sonification::default();
+    assert_eq!(sonification.frequency(0.0), 110.0);
+    assert!((sonification.frequency(0.5) - 311.127).abs() < 1e-2);
+    assert!((sonification.frequency(1.0) - 880.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_write_wav() {
+    let zeroes = crate::Zeroes::load();
+    let sonification = Sonification {
+        sample_rate: 8000,
+        ..Default::default()
+    };
+    let path = std::env::temp_dir().join("zeroes_test_write.wav");
+    sonification.write_wav(zeroes.slice(..4), &path).unwrap();
+
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().sample_rate, 8000);
+    assert_eq!(reader.len() as usize, 4 * sonification.beat_samples());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,175 @@
+//! Spacing statistics of the zeroes, and the GUE curves they are
+//! conjectured to follow.
+//!
+//! Everything returns plain `(x, y)` series so sketches can plot them
+//! directly.
+
+use std::f64::consts::PI;
+
+use crate::riemann_siegel::theta;
+
+/// Points of a curve or the centres and heights of histogram bins
+pub type Series = Vec<(f64, f64)>;
+
+/// Smooth part of the Riemann-von Mangoldt formula, the expected number
+/// of zeroes with height up to `t`
+pub fn smooth_count(t: f64) -> f64 {
+    theta(t) / PI + 1.0
+}
+
+/// Unfold heights so the zeroes have mean spacing one everywhere
+pub fn unfold(heights: &[f64]) -> Vec<f64> {
+    heights.iter().map(|&t| smooth_count(t)).collect()
+}
+
+/// Nearest neighbour spacings of the unfolded zeroes, with mean one
+pub fn normalised_spacings(heights: &[f64]) -> Vec<f64> {
+    unfold(heights).windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+/// Histogram of `values` over [0, max) with `bins` bins, normalised
+/// as a probability density so it can be compared with the reference
+/// curves. Values outside the range still count towards the total.
+/// Empty when there are no values, no bins or no range.
+pub fn density_histogram(values: &[f64], bins: usize, max: f64) -> Series {
+    if !has_bins(values.len(), bins, max) {
+        return Series::new();
+    }
+    let width = max / bins as f64;
+    let mut counts = vec![0usize; bins];
+    values
+        .iter()
+        .filter(|&&v| (0.0..max).contains(&v))
+        .for_each(|&v| counts[((v / width) as usize).min(bins - 1)] += 1);
+
+    let norm = (values.len() as f64 * width).recip();
+    counts
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| ((i as f64 + 0.5) * width, c as f64 * norm))
+        .collect()
+}
+
+/// Nearest neighbour spacing distribution of the zeroes at `heights`
+pub fn spacing_histogram(heights: &[f64], bins: usize, max: f64) -> Series {
+    density_histogram(&normalised_spacings(heights), bins, max)
+}
+
+/// Montgomery pair correlation estimate, the density of differences
+/// between all pairs of unfolded zeroes up to `max` apart. Tends to one
+/// for large differences, as for uncorrelated points. Empty like
+/// `density_histogram`.
+pub fn pair_correlation(heights: &[f64], bins: usize, max: f64) -> Series {
+    if !has_bins(heights.len(), bins, max) {
+        return Series::new();
+    }
+    let unfolded = unfold(heights);
+    let width = max / bins as f64;
+    let mut counts = vec![0usize; bins];
+    for (i, &x) in unfolded.iter().enumerate() {
+        for &y in unfolded[i + 1..].iter().take_while(|&&y| y - x < max) {
+            counts[(((y - x) / width) as usize).min(bins - 1)] += 1;
+        }
+    }
+
+    let norm = (unfolded.len() as f64 * width).recip();
+    counts
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| ((i as f64 + 0.5) * width, c as f64 * norm))
+        .collect()
+}
+
+/// Whether a histogram of `count` values in `bins` bins over [0, max) is
+/// possible
+fn has_bins(count: usize, bins: usize, max: f64) -> bool {
+    count > 0 && bins > 0 && max.is_finite() && max > 0.0
+}
+
+/// GUE Wigner surmise for the nearest neighbour spacing density
+pub fn wigner_surmise(s: f64) -> f64 {
+    32.0 / (PI * PI) * s * s * (-4.0 * s * s / PI).exp()
+}
+
+/// Pair correlation of the GUE sine kernel, 1 - (sin(pi u) / (pi u))^2
+pub fn sine_kernel(u: f64) -> f64 {
+    if u == 0.0 {
+        0.0
+    } else {
+        let x = PI * u;
+        1.0 - (x.sin() / x).powi(2)
+    }
+}
+
+/// Sample `f` at `samples` evenly spaced points of [0, max]
+pub fn reference_curve(f: fn(f64) -> f64, max: f64, samples: usize) -> Series {
+    (0..samples)
+        .map(|i| {
+            let x = max * i as f64 / (samples - 1).max(1) as f64;
+            (x, f(x))
+        })
+        .collect()
+}
+
+#[test]
+fn test_spacings_have_mean_one() {
+    let zeroes = crate::Zeroes::load();
+    let spacings = normalised_spacings(&zeroes.heights());
+    let mean = spacings.iter().sum::<f64>() / spacings.len() as f64;
+    assert!((mean - 1.0).abs() < 0.01);
+}
+
+#[test]
+fn test_spacing_histogram_near_wigner_surmise() {
+    let zeroes = crate::Zeroes::load();
+    let histogram = spacing_histogram(&zeroes.heights(), 30, 3.0);
+    let width = 0.1;
+
+    let area: f64 = histogram.iter().map(|(_, y)| y * width).sum();
+    assert!((area - 1.0).abs() < 0.01);
+
+    let distance: f64 = histogram
+        .iter()
+        .map(|&(x, y)| (y - wigner_surmise(x)).abs() * width)
+        .sum();
+    assert!(distance < 0.1);
+}
+
+#[test]
+fn test_pair_correlation_near_sine_kernel() {
+    let zeroes = crate::Zeroes::load();
+    let correlation = pair_correlation(&zeroes.heights(), 20, 4.0);
+    // repulsion at short range, uncorrelated at long range
+    assert!(correlation[0].1 < 0.1);
+    let tail = &correlation[10..];
+    let tail_mean = tail.iter().map(|(_, y)| y).sum::<f64>() / tail.len() as f64;
+    assert!((tail_mean - 1.0).abs() < 0.05);
+
+    let error = correlation
+        .iter()
+        .map(|&(u, y)| (y - sine_kernel(u)).abs())
+        .fold(0.0, f64::max);
+    assert!(error < 0.15);
+}
+
+#[test]
+fn test_reference_curves() {
+    let surmise = reference_curve(wigner_surmise, 5.0, 501);
+    let area: f64 = surmise.iter().map(|(_, y)| y * 0.01).sum();
+    assert!((area - 1.0).abs() < 1e-3);
+    assert_eq!(sine_kernel(0.0), 0.0);
+    assert!((sine_kernel(1.0) - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_degenerate_histograms_are_empty() {
+    let values = [0.5, 1.0, 1.5];
+    assert!(density_histogram(&values, 0, 2.0).is_empty());
+    assert!(density_histogram(&[], 10, 2.0).is_empty());
+    assert!(density_histogram(&values, 10, 0.0).is_empty());
+    assert!(density_histogram(&values, 10, f64::NAN).is_empty());
+    assert!(pair_correlation(&[20.0, 21.0], 0, 2.0).is_empty());
+    assert!(pair_correlation(&[], 10, 2.0).is_empty());
+    assert!(pair_correlation(&[20.0, 21.0], 10, -1.0).is_empty());
+    assert_eq!(density_histogram(&values, 2, 2.0).len(), 2);
+}
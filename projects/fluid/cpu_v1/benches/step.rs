@@ -0,0 +1,107 @@
+use cpu_v1::{
+    fluid_object::{DensColor, DyeDisplay, FluidCube},
+    particles::{Integrator, Interpolation, Particles},
+    pressure::PressureSolver,
+    render::FluidRender,
+    FluidSim,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nannou::prelude::{vec2, Rect};
+use vector_field::{
+    lic::{white_noise, Lic},
+    streamlines::Streamlines,
+};
+
+/// A cube with a few swirls of dye already going, so the solver isn't
+/// working on an empty field
+fn stirred(size: usize, parallel: bool) -> FluidCube {
+    let rect = Rect::from_w_h(size as f32, size as f32);
+    let mut fluid = FluidCube::new((size, size));
+    fluid.set_parallel(parallel);
+    for i in 0..8 {
+        let angle = i as f32 * 0.8;
+        let pos = 0.25 * size as f32 * vec2(angle.cos(), angle.sin());
+        fluid.add_density(pos, 50.0, rect);
+        fluid.add_velocity(pos, vec2(-angle.sin(), angle.cos()), rect);
+    }
+    fluid.step(0.0001, 0.0001, 0.1, 4);
+    fluid
+}
+
+fn bench_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.sample_size(20);
+    for size in [256, 512] {
+        for (name, parallel) in [("serial", false), ("parallel", true)] {
+            let mut fluid = stirred(size, parallel);
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |b, _| {
+                b.iter(|| fluid.step(0.0001, 0.0001, 0.1, 20))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Each pressure solver taking steps without diffusion to the same
+/// tolerance
+fn bench_pressure(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pressure");
+    group.sample_size(10);
+    let size = 256;
+    for method in PressureSolver::ALL {
+        let mut fluid = stirred(size, true);
+        fluid.set_pressure_solver(method, 1e-4);
+        group.bench_with_input(BenchmarkId::new(method.name(), size), &size, |b, _| {
+            b.iter(|| fluid.step(0.0, 0.0, 0.1, 2000))
+        });
+    }
+    group.finish();
+}
+
+/// Moving fifty thousand tracers with the cheapest and dearest sampling
+fn bench_particles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("particles");
+    let size = 256;
+    let rect = Rect::from_w_h(size as f32, size as f32);
+    let fluid = stirred(size, true);
+    for (interpolation, integrator) in [
+        (Interpolation::Bilinear, Integrator::Rk2),
+        (Interpolation::Bicubic, Integrator::Rk4),
+    ] {
+        let mut particles = Particles::new(50_000);
+        particles.seed_random(50_000, rect);
+        particles.interpolation = interpolation;
+        particles.integrator = integrator;
+        let name = format!("{} {}", interpolation.name(), integrator.name());
+        group.bench_function(name, |b| b.iter(|| particles.step(&fluid, 0.1)));
+    }
+    group.finish();
+}
+
+/// Drawing a window's worth of pixels from the grid
+fn bench_render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    let fluid = stirred(256, true);
+    let size = (1000, 1000);
+    let display = DyeDisplay::Density(DensColor::new(0.5, 0.5));
+    group.bench_function("dens", |b| b.iter(|| fluid.render_dens(size, display)));
+    group.bench_function("vel", |b| b.iter(|| fluid.render_vel(size, 1.0)));
+    let noise = white_noise((500, 500), 0);
+    group.bench_function("lic", |b| {
+        b.iter(|| fluid.render_lic(&Lic::default(), &noise, 1.0, size))
+    });
+    let rect = Rect::from_w_h(1000.0, 1000.0);
+    group.bench_function("streamlines", |b| {
+        b.iter(|| fluid.streamlines(&Streamlines::default(), rect))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_step,
+    bench_pressure,
+    bench_particles,
+    bench_render
+);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Runs a scenario without a window, writing its frames as PNGs
+//!
+//! Usage: `headless <scenario> [output directory]`, the frames go to
+//! `frames` by default. See `cpu_v1::scenario` for the file format.
+
+use std::{env, fs, path::PathBuf, process, time::Instant};
+
+use cpu_v1::scenario::Scenario;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("usage: headless <scenario> [output directory]");
+            process::exit(2);
+        }
+    };
+    let out = args
+        .next()
+        .map_or_else(|| PathBuf::from("frames"), PathBuf::from);
+
+    let scenario = Scenario::load(&path).unwrap_or_else(|e| {
+        eprintln!("can't load {}: {}", path.display(), e);
+        process::exit(1);
+    });
+    if let Err(e) = fs::create_dir_all(&out) {
+        eprintln!("can't create {}: {}", out.display(), e);
+        process::exit(1);
+    }
+
+    let start = Instant::now();
+    let frames = scenario.steps / scenario.every.max(1);
+    let written: Result<(), String> = scenario.run(|number, image| {
+        let file = out.join(format!("frame-{:05}.png", number));
+        image
+            .save(&file)
+            .map_err(|e| format!("can't save {}: {}", file.display(), e))?;
+        println!("frame {} of {}", number + 1, frames);
+        Ok(())
+    });
+    if let Err(e) = written {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    println!("{} frames in {:.1}s", frames, start.elapsed().as_secs_f32());
+}
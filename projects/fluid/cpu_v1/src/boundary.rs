@@ -0,0 +1,211 @@
+//! What happens to the fluid at the edges of the grid
+//!
+//! The outermost ring of cells are ghost cells, filled in from the
+//! interior after every sweep of the solver. Each edge has its own mode,
+//! which decides how the ghost cells are filled for each quantity.
+
+use nannou::prelude::{vec2, Vec2};
+use ndarray::Array2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// Solid wall the fluid sticks to, velocity is zero at the wall
+    NoSlip,
+    /// Solid wall the fluid slides along, only the normal velocity is zero
+    FreeSlip,
+    /// Wraps around to the opposite edge, like the particle sketches do.
+    /// Set on one edge of an axis it makes the opposite edge periodic too.
+    Periodic,
+    /// Outflow, everything leaves with zero gradient across the edge
+    Open,
+}
+
+impl Boundary {
+    pub const ALL: [Boundary; 4] = [
+        Boundary::NoSlip,
+        Boundary::FreeSlip,
+        Boundary::Periodic,
+        Boundary::Open,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Boundary::NoSlip => "no slip",
+            Boundary::FreeSlip => "free slip",
+            Boundary::Periodic => "periodic",
+            Boundary::Open => "open",
+        }
+    }
+}
+
+/// Mode of each edge of the grid
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Boundaries {
+    pub left: Boundary,
+    pub right: Boundary,
+    pub bottom: Boundary,
+    pub top: Boundary,
+}
+
+impl Boundaries {
+    pub fn all(boundary: Boundary) -> Self {
+        Self {
+            left: boundary,
+            right: boundary,
+            bottom: boundary,
+            top: boundary,
+        }
+    }
+
+    /// The same edges with periodicity made per axis: an edge wrapping
+    /// round needs the opposite edge to wrap back, so a periodic edge
+    /// makes its opposite edge periodic too
+    pub fn paired(self) -> Self {
+        let pair = |a: Boundary, b: Boundary| {
+            if a == Boundary::Periodic || b == Boundary::Periodic {
+                (Boundary::Periodic, Boundary::Periodic)
+            } else {
+                (a, b)
+            }
+        };
+        let (left, right) = pair(self.left, self.right);
+        let (bottom, top) = pair(self.bottom, self.top);
+        Self {
+            left,
+            right,
+            bottom,
+            top,
+        }
+    }
+
+    /// Whether positions wrap around along x and along y
+    pub fn wraps(&self) -> (bool, bool) {
+        (
+            self.left == Boundary::Periodic && self.right == Boundary::Periodic,
+            self.bottom == Boundary::Periodic && self.top == Boundary::Periodic,
+        )
+    }
+}
+
+impl Default for Boundaries {
+    /// Reflecting walls all round
+    fn default() -> Self {
+        Self::all(Boundary::FreeSlip)
+    }
+}
+
+/// Which way an edge faces, so vector rules know the normal component
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Axis {
+    X,
+    Y,
+}
+
+/// Fill the ghost cells of the array. Periodic edges copy the interior
+/// cell they wrap to, the other modes get their value from `rule` given
+/// the mode, the axis the edge is normal to and the inner neighbour.
+///
+/// The left and right columns are filled first and then the bottom and
+/// top rows across the full width, so the corners follow from the ghost
+/// cells beside them, wrapping diagonally when both axes are periodic.
+fn fill_edges<A, F>(array: &mut Array2<A>, boundaries: &Boundaries, rule: F)
+where
+    A: Copy,
+    F: Fn(Boundary, Axis, A) -> A,
+{
+    let (nx, ny) = array.dim();
+    let edge = |mode, inner: A, wrapped: A| match mode {
+        Boundary::Periodic => wrapped,
+        mode => rule(mode, Axis::X, inner),
+    };
+    for j in 1..(ny - 1) {
+        array[(0, j)] = edge(boundaries.left, array[(1, j)], array[(nx - 2, j)]);
+        array[(nx - 1, j)] = edge(boundaries.right, array[(nx - 2, j)], array[(1, j)]);
+    }
+    let edge = |mode, inner: A, wrapped: A| match mode {
+        Boundary::Periodic => wrapped,
+        mode => rule(mode, Axis::Y, inner),
+    };
+    for i in 0..nx {
+        array[(i, 0)] = edge(boundaries.bottom, array[(i, 1)], array[(i, ny - 2)]);
+        array[(i, ny - 1)] = edge(boundaries.top, array[(i, ny - 2)], array[(i, 1)]);
+    }
+}
+
+/// Scalars carried by the fluid copy their inner neighbour, so nothing
+/// diffuses through walls and whatever reaches an open edge leaves
+pub(crate) fn set_scalar_boundaries(array: &mut Array2<f32>, boundaries: &Boundaries) {
+    fill_edges(array, boundaries, |_, _, inner| inner);
+}
+
+/// Pressure copies its inner neighbour at walls, so there is no pressure
+/// gradient across them, and is zero at open edges
+pub(crate) fn set_pressure_boundaries(array: &mut Array2<f32>, boundaries: &Boundaries) {
+    fill_edges(array, boundaries, |mode, _, inner| match mode {
+        Boundary::Open => -inner,
+        _ => inner,
+    });
+}
+
+/// Velocity is reversed at no-slip walls, reflected at free-slip walls,
+/// with the normal component flipped so there's no flow through them,
+/// and copied at open edges
+pub(crate) fn set_velocity_boundaries(array: &mut Array2<Vec2>, boundaries: &Boundaries) {
+    fill_edges(array, boundaries, |mode, axis, inner| match (mode, axis) {
+        (Boundary::NoSlip, _) => -inner,
+        (Boundary::FreeSlip, Axis::X) => vec2(-inner.x, inner.y),
+        (Boundary::FreeSlip, Axis::Y) => vec2(inner.x, -inner.y),
+        _ => inner,
+    });
+}
+
+#[test]
+fn test_periodic_corners_wrap() {
+    let mut array = Array2::from_shape_fn((5, 4), |(x, y)| (10 * x + y) as f32);
+    set_scalar_boundaries(&mut array, &Boundaries::all(Boundary::Periodic));
+    assert_eq!(array[(0, 1)], array[(3, 1)]);
+    assert_eq!(array[(4, 2)], array[(1, 2)]);
+    assert_eq!(array[(2, 0)], array[(2, 2)]);
+    assert_eq!(array[(0, 0)], array[(3, 2)]);
+    assert_eq!(array[(4, 3)], array[(1, 1)]);
+}
+
+#[test]
+fn test_velocity_walls() {
+    let mut array = Array2::from_elem((4, 4), vec2(1.0, 2.0));
+    let boundaries = Boundaries {
+        left: Boundary::NoSlip,
+        right: Boundary::FreeSlip,
+        bottom: Boundary::Open,
+        top: Boundary::FreeSlip,
+    };
+    set_velocity_boundaries(&mut array, &boundaries);
+    assert_eq!(array[(0, 1)], vec2(-1.0, -2.0));
+    assert_eq!(array[(3, 1)], vec2(-1.0, 2.0));
+    assert_eq!(array[(1, 0)], vec2(1.0, 2.0));
+    assert_eq!(array[(1, 3)], vec2(1.0, -2.0));
+}
+
+#[test]
+fn test_periodic_edge_pairs_with_opposite() {
+    let boundaries = Boundaries {
+        left: Boundary::Periodic,
+        right: Boundary::NoSlip,
+        bottom: Boundary::Open,
+        top: Boundary::FreeSlip,
+    }
+    .paired();
+    assert_eq!(boundaries.right, Boundary::Periodic);
+    assert_eq!(
+        (boundaries.bottom, boundaries.top),
+        (Boundary::Open, Boundary::FreeSlip)
+    );
+    assert_eq!(boundaries.wraps(), (true, false));
+
+    // the ghost cells on both sides of the wrapping axis wrap
+    let mut array = Array2::from_shape_fn((5, 4), |(x, y)| (10 * x + y) as f32);
+    set_scalar_boundaries(&mut array, &boundaries);
+    assert_eq!(array[(0, 1)], array[(3, 1)]);
+    assert_eq!(array[(4, 1)], array[(1, 1)]);
+    assert_eq!(array[(2, 0)], array[(2, 1)]);
+}
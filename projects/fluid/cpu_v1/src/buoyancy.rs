@@ -0,0 +1,91 @@
+//! Heat and smoke pushing the fluid up and down
+//!
+//! The fluid carries a temperature along like the dye. Cells hotter than
+//! the surrounding air are pushed up and the smoke's weight pulls it
+//! down, the force from Fedkiw, Stam and Jensen's "Visual Simulation of
+//! Smoke", while the temperature relaxes back towards the ambient one.
+
+use nannou::prelude::{vec2, Vec2};
+use ndarray::Array2;
+
+use crate::{for_rows, Domain, Field};
+
+/// The default has no force and no cooling, so the temperature is carried
+/// along and does nothing else
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Buoyancy {
+    /// Upward acceleration per degree above the ambient temperature
+    pub lift: f32,
+    /// Downward acceleration per unit of dye, averaged over the channels
+    pub weight: f32,
+    /// Temperature of the surrounding air, which feels no force
+    pub ambient: f32,
+    /// Fraction of the difference from the ambient temperature lost per
+    /// unit time
+    pub cooling: f32,
+}
+
+impl Buoyancy {
+    pub(crate) fn is_off(&self) -> bool {
+        self.lift == 0.0 && self.weight == 0.0
+    }
+}
+
+/// Accelerate the interior fluid cells up where they are hot and down
+/// where there is dye, over a step of `dt`
+pub(crate) fn apply_buoyancy(
+    velocity: &mut Array2<Vec2>,
+    temperature: &Array2<f32>,
+    dyes: &[Array2<f32>],
+    buoyancy: &Buoyancy,
+    dt: f32,
+    domain: &Domain,
+) {
+    if buoyancy.is_off() {
+        return;
+    }
+    let obstacles = &domain.obstacles;
+    let channels = dyes.len() as f32;
+    for_rows(velocity, domain.parallel, |x, row| {
+        let len = row.len();
+        for (y, cell) in row.iter_mut().enumerate().take(len - 1).skip(1) {
+            if obstacles.is_solid(x, y) {
+                continue;
+            }
+            let smoke = dyes.iter().map(|dye| dye[(x, y)]).sum::<f32>() / channels;
+            let heat = temperature[(x, y)] - buoyancy.ambient;
+            *cell += dt * vec2(0.0, buoyancy.lift * heat - buoyancy.weight * smoke);
+        }
+    });
+    Vec2::set_boundaries(velocity, domain);
+}
+
+/// Exponential decay of the temperature towards the ambient one
+pub(crate) fn cool(temperature: &mut Array2<f32>, buoyancy: &Buoyancy, dt: f32) {
+    if buoyancy.cooling > 0.0 {
+        let factor = (-buoyancy.cooling * dt).exp();
+        let ambient = buoyancy.ambient;
+        temperature.mapv_inplace(|t| ambient + (t - ambient) * factor);
+    }
+}
+
+#[test]
+fn test_cooling_towards_ambient() {
+    let buoyancy = Buoyancy {
+        ambient: 20.0,
+        cooling: 0.5,
+        ..Buoyancy::default()
+    };
+    let mut temperature = Array2::from_shape_fn((4, 4), |(x, _)| 10.0 * x as f32);
+    let start = temperature.clone();
+    cool(&mut temperature, &buoyancy, 2.0);
+    let factor = (-1.0f32).exp();
+    for (t, t0) in temperature.iter().zip(&start) {
+        assert!(
+            (t - 20.0 - (t0 - 20.0) * factor).abs() < 1e-4,
+            "{} {}",
+            t,
+            t0
+        );
+    }
+}
@@ -0,0 +1,607 @@
+//! Snapshots of a `FluidCube` that can be written to disk and restored
+//!
+//! Everything a step reads is kept: the dye, temperature and velocity,
+//! their previous buffers and the last pressure, which the next solve
+//! starts from, as well as the solver settings and the obstacles. So a
+//! restored cube steps exactly like the one that was saved.
+//!
+//! The file is little endian. The header is the magic `FLUIDCKP`, the
+//! format version, the grid size and the number of dye channels as
+//! `u32`, then the settings, the obstacles and the obstacle image packed
+//! eight cells to a byte. The fields follow as raw `f32`, each in `(x, y)`
+//! order with y changing fastest and velocities as x then y.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use nannou::prelude::{vec2, Rect, Vec2};
+use ndarray::Array2;
+
+use crate::{
+    advection::Advection,
+    boundary::{Boundaries, Boundary},
+    buoyancy::Buoyancy,
+    obstacle::{Obstacle, Shape},
+    pressure::PressureSolver,
+    sample, Field,
+};
+
+const MAGIC: &[u8; 8] = b"FLUIDCKP";
+const VERSION: u32 = 1;
+/// Largest grid and number of dye channels read back, so a corrupt
+/// header is an error rather than an enormous allocation
+const MAX_CELLS: usize = 1 << 26;
+const MAX_CHANNELS: u32 = 16;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Format(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "io error: {}", e),
+            CheckpointError::Format(e) => write!(f, "bad checkpoint: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+fn format_error(msg: &str) -> CheckpointError {
+    CheckpointError::Format(msg.to_string())
+}
+
+/// The state of a `FluidCube`, see `FluidCube::checkpoint` and
+/// `FluidCube::restore`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub(crate) dyes: Vec<Array2<f32>>,
+    pub(crate) dye_prev: Array2<f32>,
+    pub(crate) velocity: Array2<Vec2>,
+    pub(crate) velocity_prev: Array2<Vec2>,
+    pub(crate) temperature: Array2<f32>,
+    pub(crate) curl: Array2<f32>,
+    pub(crate) pressure: Array2<f32>,
+    pub(crate) vorticity: f32,
+    pub(crate) velocity_dissipation: f32,
+    pub(crate) dye_dissipation: f32,
+    pub(crate) buoyancy: Buoyancy,
+    pub(crate) boundaries: Boundaries,
+    pub(crate) advection: Advection,
+    pub(crate) pressure_solver: PressureSolver,
+    pub(crate) tolerance: f32,
+    pub(crate) parallel: bool,
+    pub(crate) obstacles: Vec<Obstacle>,
+    pub(crate) obstacle_image: Option<Array2<bool>>,
+}
+
+impl Checkpoint {
+    /// Number of cells along each axis, including the boundary ring
+    pub fn size(&self) -> (usize, usize) {
+        self.velocity.dim()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.dyes.len()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CheckpointError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), CheckpointError> {
+        let out = &mut out;
+        let (nx, ny) = self.size();
+        out.write_all(MAGIC)?;
+        put_u32s(
+            out,
+            &[VERSION, nx as u32, ny as u32, self.dyes.len() as u32],
+        )?;
+
+        put_f32s(
+            out,
+            [
+                self.vorticity,
+                self.velocity_dissipation,
+                self.dye_dissipation,
+                self.buoyancy.lift,
+                self.buoyancy.weight,
+                self.buoyancy.ambient,
+                self.buoyancy.cooling,
+                self.tolerance,
+            ],
+        )?;
+        let Boundaries {
+            left,
+            right,
+            bottom,
+            top,
+        } = self.boundaries;
+        out.write_all(&[
+            index_of(&Boundary::ALL, left),
+            index_of(&Boundary::ALL, right),
+            index_of(&Boundary::ALL, bottom),
+            index_of(&Boundary::ALL, top),
+            index_of(&Advection::ALL, self.advection),
+            index_of(&PressureSolver::ALL, self.pressure_solver),
+            self.parallel as u8,
+        ])?;
+
+        put_u32s(out, &[self.obstacles.len() as u32])?;
+        for obstacle in &self.obstacles {
+            put_f32s(out, [obstacle.velocity.x, obstacle.velocity.y])?;
+            match &obstacle.shape {
+                Shape::Circle { centre, radius } => {
+                    out.write_all(&[0])?;
+                    put_f32s(out, [centre.x, centre.y, *radius])?;
+                }
+                Shape::Rect(rect) => {
+                    out.write_all(&[1])?;
+                    put_f32s(out, [rect.left(), rect.bottom(), rect.right(), rect.top()])?;
+                }
+                Shape::Polygon(vertices) => {
+                    out.write_all(&[2])?;
+                    put_u32s(out, &[vertices.len() as u32])?;
+                    put_f32s(out, vertices.iter().flat_map(|v| [v.x, v.y]))?;
+                }
+            }
+        }
+        match &self.obstacle_image {
+            Some(image) => {
+                out.write_all(&[1])?;
+                out.write_all(&pack_bits(image))?;
+            }
+            None => out.write_all(&[0])?,
+        }
+
+        for array in self.scalars() {
+            put_f32s(out, array.iter().copied())?;
+        }
+        for array in [&self.velocity, &self.velocity_prev] {
+            put_f32s(out, array.iter().flat_map(|v| [v.x, v.y]))?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut input: R) -> Result<Self, CheckpointError> {
+        let input = &mut input;
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format_error("missing header"));
+        }
+        let [version, nx, ny, channels] = get_u32s::<4>(input)?;
+        if version != VERSION {
+            return Err(format_error("unknown version"));
+        }
+        let size = (nx as usize, ny as usize);
+        if nx < 3 || ny < 3 || channels == 0 {
+            return Err(format_error("grid too small or no dye"));
+        }
+        let cells = size
+            .0
+            .checked_mul(size.1)
+            .filter(|&cells| cells <= MAX_CELLS)
+            .ok_or_else(|| format_error("grid too big"))?;
+        if channels > MAX_CHANNELS {
+            return Err(format_error("too many dye channels"));
+        }
+
+        let [vorticity, velocity_dissipation, dye_dissipation, lift, weight, ambient, cooling, tolerance] =
+            get_f32s::<8>(input)?;
+        let mut modes = [0; 7];
+        input.read_exact(&mut modes)?;
+        let boundary = |i: usize| from_index(&Boundary::ALL, modes[i], "boundary");
+        let boundaries = Boundaries {
+            left: boundary(0)?,
+            right: boundary(1)?,
+            bottom: boundary(2)?,
+            top: boundary(3)?,
+        };
+
+        let [count] = get_u32s::<1>(input)?;
+        let mut obstacles = Vec::new();
+        for _ in 0..count {
+            let [vx, vy] = get_f32s::<2>(input)?;
+            let shape = match get_u8(input)? {
+                0 => {
+                    let [x, y, radius] = get_f32s::<3>(input)?;
+                    Shape::Circle {
+                        centre: vec2(x, y),
+                        radius,
+                    }
+                }
+                1 => {
+                    let [left, bottom, right, top] = get_f32s::<4>(input)?;
+                    Shape::Rect(Rect::from_corners(vec2(left, bottom), vec2(right, top)))
+                }
+                2 => {
+                    let [len] = get_u32s::<1>(input)?;
+                    let coords = (len as usize)
+                        .checked_mul(2)
+                        .ok_or_else(|| format_error("polygon too big"))
+                        .and_then(|len| get_f32_vec(input, len))?;
+                    Shape::Polygon(coords.chunks(2).map(|c| vec2(c[0], c[1])).collect())
+                }
+                _ => return Err(format_error("unknown obstacle shape")),
+            };
+            obstacles.push(Obstacle {
+                shape,
+                velocity: vec2(vx, vy),
+            });
+        }
+        let obstacle_image = match get_u8(input)? {
+            0 => None,
+            1 => {
+                let mut bytes = vec![0; cells.div_ceil(8)];
+                input.read_exact(&mut bytes)?;
+                Some(unpack_bits(&bytes, size))
+            }
+            _ => return Err(format_error("bad obstacle image flag")),
+        };
+
+        let mut scalar = || -> Result<Array2<f32>, CheckpointError> {
+            let values = get_f32_vec(input, cells)?;
+            Array2::from_shape_vec(size, values).map_err(|_| format_error("grid size mismatch"))
+        };
+        let dyes = (0..channels)
+            .map(|_| scalar())
+            .collect::<Result<Vec<_>, _>>()?;
+        let dye_prev = scalar()?;
+        let temperature = scalar()?;
+        let curl = scalar()?;
+        let pressure = scalar()?;
+        let mut vector = || -> Result<Array2<Vec2>, CheckpointError> {
+            let values = get_f32_vec(input, 2 * cells)?;
+            let vectors = values.chunks(2).map(|c| vec2(c[0], c[1])).collect();
+            Array2::from_shape_vec(size, vectors).map_err(|_| format_error("grid size mismatch"))
+        };
+        let velocity = vector()?;
+        let velocity_prev = vector()?;
+
+        Ok(Self {
+            dyes,
+            dye_prev,
+            velocity,
+            velocity_prev,
+            temperature,
+            curl,
+            pressure,
+            vorticity,
+            velocity_dissipation,
+            dye_dissipation,
+            buoyancy: Buoyancy {
+                lift,
+                weight,
+                ambient,
+                cooling,
+            },
+            boundaries,
+            advection: from_index(&Advection::ALL, modes[4], "advection")?,
+            pressure_solver: from_index(&PressureSolver::ALL, modes[5], "pressure solver")?,
+            tolerance,
+            parallel: modes[6] != 0,
+            obstacles,
+            obstacle_image,
+        })
+    }
+
+    /// The checkpoint on a grid of `size`, with every field interpolated
+    /// bilinearly between the cell centres of the whole grid, boundary ring
+    /// included. Velocities are in units of the grid's longest side, so
+    /// they carry over as they are. The obstacle shapes are in window
+    /// coordinates and don't change.
+    pub fn resample(&self, size: (usize, usize)) -> Self {
+        let scalar = |array: &Array2<f32>| resample(array, size);
+        Self {
+            dyes: self.dyes.iter().map(scalar).collect(),
+            dye_prev: scalar(&self.dye_prev),
+            velocity: resample(&self.velocity, size),
+            velocity_prev: resample(&self.velocity_prev, size),
+            temperature: scalar(&self.temperature),
+            curl: scalar(&self.curl),
+            pressure: scalar(&self.pressure),
+            obstacles: self.obstacles.clone(),
+            obstacle_image: self.obstacle_image.as_ref().map(|image| {
+                let (nx, ny) = image.dim();
+                Array2::from_shape_fn(size, |(x, y)| {
+                    let pos = cell_in(image.dim(), size, (x, y));
+                    image[((pos.x as usize).min(nx - 1), (pos.y as usize).min(ny - 1))]
+                })
+            }),
+            ..*self
+        }
+    }
+
+    fn scalars(&self) -> impl Iterator<Item = &Array2<f32>> {
+        self.dyes.iter().chain([
+            &self.dye_prev,
+            &self.temperature,
+            &self.curl,
+            &self.pressure,
+        ])
+    }
+}
+
+/// Where the centre of cell `(x, y)` of a grid of `size` falls on a grid
+/// of `dim` stretched over the same area, in cells
+fn cell_in(dim: (usize, usize), size: (usize, usize), (x, y): (usize, usize)) -> Vec2 {
+    (vec2(x as f32, y as f32) + 0.5) * vec2(dim.0 as f32, dim.1 as f32)
+        / vec2(size.0 as f32, size.1 as f32)
+}
+
+fn resample<A: Field>(array: &Array2<A>, size: (usize, usize)) -> Array2<A> {
+    if array.dim() == size {
+        return array.clone();
+    }
+    Array2::from_shape_fn(size, |cell| sample(array, cell_in(array.dim(), size, cell)))
+}
+
+fn index_of<T: PartialEq>(all: &[T], item: T) -> u8 {
+    all.iter()
+        .position(|a| *a == item)
+        .expect("every variant is in ALL") as u8
+}
+
+fn from_index<T: Copy>(all: &[T], index: u8, what: &str) -> Result<T, CheckpointError> {
+    all.get(index as usize)
+        .copied()
+        .ok_or_else(|| CheckpointError::Format(format!("unknown {}", what)))
+}
+
+fn put_u32s<W: Write>(out: &mut W, values: &[u32]) -> io::Result<()> {
+    values
+        .iter()
+        .try_for_each(|v| out.write_all(&v.to_le_bytes()))
+}
+
+fn put_f32s<W: Write>(out: &mut W, values: impl IntoIterator<Item = f32>) -> io::Result<()> {
+    values
+        .into_iter()
+        .try_for_each(|v| out.write_all(&v.to_le_bytes()))
+}
+
+fn get_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn get_u32s<const N: usize>(input: &mut impl Read) -> io::Result<[u32; N]> {
+    let mut bytes = vec![0; 4 * N];
+    input.read_exact(&mut bytes)?;
+    let mut values = [0; N];
+    for (value, chunk) in values.iter_mut().zip(bytes.chunks(4)) {
+        *value = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    Ok(values)
+}
+
+fn get_f32s<const N: usize>(input: &mut impl Read) -> io::Result<[f32; N]> {
+    Ok(get_u32s::<N>(input)?.map(f32::from_bits))
+}
+
+/// `len` floats, only read as far as the input goes so a corrupt length
+/// runs into the end of the file instead of allocating it all up front
+fn get_f32_vec<R: Read>(input: &mut R, len: usize) -> Result<Vec<f32>, CheckpointError> {
+    let expected = len
+        .checked_mul(4)
+        .ok_or_else(|| format_error("too many values"))?;
+    let mut bytes = Vec::new();
+    input
+        .by_ref()
+        .take(expected as u64)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() != expected {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes
+        .chunks(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+fn pack_bits(cells: &Array2<bool>) -> Vec<u8> {
+    let mut bytes = vec![0; cells.len().div_ceil(8)];
+    for (i, _) in cells.iter().enumerate().filter(|(_, &cell)| cell) {
+        bytes[i / 8] |= 1 << (i % 8);
+    }
+    bytes
+}
+
+fn unpack_bits(bytes: &[u8], size: (usize, usize)) -> Array2<bool> {
+    let cells = (0..size.0 * size.1)
+        .map(|i| bytes[i / 8] & (1 << (i % 8)) != 0)
+        .collect();
+    Array2::from_shape_vec(size, cells).expect("a bit for every cell")
+}
+
+/// A stirred cube with every setting away from its default, obstacles of
+/// each shape and a few more steps of flow
+#[cfg(test)]
+fn busy_cube(rect: Rect) -> crate::fluid_object::FluidCube {
+    use crate::FluidSim;
+    use nannou::image::{DynamicImage, GrayImage, Luma};
+
+    let mut fluid = crate::stirred_cube((40, 30), 3);
+    fluid.set_boundaries(Boundaries {
+        left: Boundary::Periodic,
+        right: Boundary::Periodic,
+        bottom: Boundary::NoSlip,
+        top: Boundary::Open,
+    });
+    fluid.set_advection(Advection::MacCormack);
+    fluid.set_pressure_solver(PressureSolver::Multigrid, 1e-4);
+    fluid.set_vorticity(0.2);
+    fluid.set_dissipation(0.01, 0.02);
+    fluid.set_buoyancy(Buoyancy {
+        lift: 0.01,
+        weight: 0.005,
+        ambient: 1.0,
+        cooling: 0.1,
+    });
+    fluid.add_obstacle(
+        Obstacle::new(Shape::Circle {
+            centre: vec2(-20.0, 0.0),
+            radius: 8.0,
+        }),
+        rect,
+    );
+    fluid.add_obstacle(
+        Obstacle::new(Shape::Rect(Rect::from_x_y_w_h(30.0, -20.0, 10.0, 6.0))),
+        rect,
+    );
+    let triangle = Shape::Polygon(vec![vec2(0.0, 20.0), vec2(10.0, 20.0), vec2(5.0, 28.0)]);
+    let index = fluid.add_obstacle(Obstacle::new(triangle), rect);
+    let image = GrayImage::from_fn(8, 8, |x, y| Luma([if x + y == 7 { 0 } else { 255 }]));
+    fluid.set_obstacle_image(&DynamicImage::ImageLuma8(image), rect);
+    for i in 0..4 {
+        fluid.add_dye(vec2(10.0 * i as f32, -5.0), &[1.0, 0.5, 0.2], rect);
+        fluid.add_velocity(vec2(-10.0, 5.0 * i as f32), vec2(0.5, 0.3), rect);
+        fluid.add_heat(vec2(0.0, -25.0), &Default::default(), 5.0, rect);
+        fluid.move_obstacle(index, vec2(1.0, 0.0), 0.1, rect);
+        fluid.step(0.001, 0.0001, 0.1, 10);
+    }
+    fluid
+}
+
+#[cfg(test)]
+fn to_bytes(checkpoint: &Checkpoint) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    checkpoint.write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_restore_is_bit_exact() {
+    use crate::{fluid_object::FluidCube, FluidSim};
+
+    let rect = Rect::from_w_h(80.0, 60.0);
+    let mut fluid = busy_cube(rect);
+    let checkpoint = fluid.checkpoint();
+    let bytes = to_bytes(&checkpoint);
+    let read = Checkpoint::read(&bytes[..]).unwrap();
+    assert_eq!(read, checkpoint);
+    assert_eq!(to_bytes(&read), bytes);
+
+    let mut restored = FluidCube::restore(read, (40, 30), rect);
+    assert_eq!(restored.solid(), fluid.solid());
+    for _ in 0..5 {
+        fluid.step(0.001, 0.0001, 0.1, 10);
+        restored.step(0.001, 0.0001, 0.1, 10);
+    }
+    assert_eq!(
+        to_bytes(&restored.checkpoint()),
+        to_bytes(&fluid.checkpoint())
+    );
+
+    let path = std::env::temp_dir().join(format!("cpu_v1_checkpoint_{}.bin", std::process::id()));
+    checkpoint.save(&path).unwrap();
+    assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_resample() {
+    use crate::{fluid_object::FluidCube, FluidSim};
+
+    let rect = Rect::from_w_h(80.0, 60.0);
+    let mut checkpoint = FluidCube::new((10, 10)).checkpoint();
+    checkpoint.dyes[0] = Array2::from_shape_fn((10, 10), |(x, _)| x as f32 + 0.5);
+    checkpoint.velocity.fill(vec2(0.25, -0.5));
+    checkpoint.obstacle_image = Some(Array2::from_shape_fn((10, 10), |(x, _)| x < 5));
+
+    let resampled = checkpoint.resample((20, 15));
+    assert_eq!(resampled.size(), (20, 15));
+    assert_eq!(resampled.tolerance, checkpoint.tolerance);
+    assert!(resampled.velocity.iter().all(|&v| v == vec2(0.25, -0.5)));
+    // the dye was its position along x, away from the edges it still is
+    for x in 2..18 {
+        let expected = (x as f32 + 0.5) / 2.0;
+        assert!((resampled.dyes[0][(x, 7)] - expected).abs() < 1e-5);
+    }
+    let image = resampled.obstacle_image.as_ref().unwrap();
+    assert!(image[(9, 0)] && !image[(10, 14)]);
+
+    let mut fluid = FluidCube::restore(checkpoint, (20, 15), rect);
+    assert_eq!(fluid.size(), (20, 15));
+    fluid.step(0.0, 0.0, 0.1, 4);
+    let same = fluid.resized((20, 15), rect);
+    assert_eq!(to_bytes(&same.checkpoint()), to_bytes(&fluid.checkpoint()));
+}
+
+#[test]
+fn test_bad_checkpoints() {
+    let bytes = to_bytes(&crate::fluid_object::FluidCube::new((6, 5)).checkpoint());
+    assert!(Checkpoint::read(&bytes[..]).is_ok());
+
+    let mut wrong = bytes.clone();
+    wrong[0] = b'X';
+    assert!(matches!(
+        Checkpoint::read(&wrong[..]),
+        Err(CheckpointError::Format(_))
+    ));
+    let mut version = bytes.clone();
+    version[8] = 9;
+    assert!(matches!(
+        Checkpoint::read(&version[..]),
+        Err(CheckpointError::Format(_))
+    ));
+    match Checkpoint::read(&bytes[..bytes.len() - 1]) {
+        Err(CheckpointError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("{:?}", other.map(|c| c.size())),
+    }
+}
+
+#[test]
+fn test_corrupt_sizes_are_errors() {
+    let mut checkpoint = crate::fluid_object::FluidCube::new((6, 5)).checkpoint();
+    let bytes = to_bytes(&checkpoint);
+    // nx, ny and the channels follow the magic and version
+    for (at, value) in [(12, u32::MAX), (16, 1 << 30), (20, u32::MAX)] {
+        let mut wrong = bytes.clone();
+        wrong[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        assert!(
+            matches!(
+                Checkpoint::read(&wrong[..]),
+                Err(CheckpointError::Format(_))
+            ),
+            "{} {}",
+            at,
+            value
+        );
+    }
+
+    let triangle = Shape::Polygon(vec![vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)]);
+    checkpoint.obstacles.push(Obstacle::new(triangle));
+    let mut wrong = to_bytes(&checkpoint);
+    assert!(Checkpoint::read(&wrong[..]).is_ok());
+    // the vertex count is after the header, the settings, the modes, the
+    // obstacle count, its velocity and its shape
+    let at = 24 + 4 * 8 + 7 + 4 + 4 * 2 + 1;
+    wrong[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    match Checkpoint::read(&wrong[..]) {
+        Err(CheckpointError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("{:?}", other.map(|c| c.size())),
+    }
+}
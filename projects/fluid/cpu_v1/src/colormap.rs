@@ -0,0 +1,253 @@
+//! Colour maps for scalar fields
+//!
+//! The perceptually uniform maps are matplotlib's, and turbo is Google's
+//! rainbow replacement, each sampled at ten evenly spaced points and
+//! interpolated linearly between them, which is close enough to the full
+//! tables to keep their even steps in lightness. The diverging maps are
+//! for signed fields like curl and pressure, light in the middle and
+//! blue below it. Any other gradient can be built from colour stops.
+
+use std::fmt;
+
+/// Red, green and blue in [0, 1]
+pub type Rgb = [f32; 3];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorMap {
+    #[default]
+    Viridis,
+    Magma,
+    Inferno,
+    Cividis,
+    Turbo,
+    /// Kenneth Moreland's smooth blue to red through grey
+    CoolWarm,
+    /// ColorBrewer's RdBu, reversed so it goes from blue to red
+    BlueRed,
+}
+
+impl ColorMap {
+    pub const ALL: [ColorMap; 7] = [
+        ColorMap::Viridis,
+        ColorMap::Magma,
+        ColorMap::Inferno,
+        ColorMap::Cividis,
+        ColorMap::Turbo,
+        ColorMap::CoolWarm,
+        ColorMap::BlueRed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMap::Viridis => "viridis",
+            ColorMap::Magma => "magma",
+            ColorMap::Inferno => "inferno",
+            ColorMap::Cividis => "cividis",
+            ColorMap::Turbo => "turbo",
+            ColorMap::CoolWarm => "coolwarm",
+            ColorMap::BlueRed => "blue-red",
+        }
+    }
+
+    /// Whether the middle of the map is special, for fields with a sign
+    pub fn is_diverging(&self) -> bool {
+        matches!(self, ColorMap::CoolWarm | ColorMap::BlueRed)
+    }
+
+    fn hex(&self) -> &'static [u32] {
+        match self {
+            ColorMap::Viridis => &[
+                0x440154, 0x482878, 0x3e4989, 0x31688e, 0x26828e, 0x1f9e89, 0x35b779, 0x6ece58,
+                0xb5de2b, 0xfde725,
+            ],
+            ColorMap::Magma => &[
+                0x000004, 0x180f3d, 0x440f76, 0x721f81, 0x9e2f7f, 0xcd4071, 0xf1605d, 0xfd9668,
+                0xfeca8d, 0xfcfdbf,
+            ],
+            ColorMap::Inferno => &[
+                0x000004, 0x1b0c41, 0x4a0c6b, 0x781c6d, 0xa52c60, 0xcf4446, 0xed6925, 0xfb9b06,
+                0xf7d13d, 0xfcffa4,
+            ],
+            ColorMap::Cividis => &[
+                0x00224e, 0x123570, 0x3b496c, 0x575d6d, 0x707173, 0x8a8779, 0xa69d75, 0xc4b56c,
+                0xe4cf5b, 0xfee838,
+            ],
+            ColorMap::Turbo => &[
+                0x30123b, 0x4662d7, 0x36aaf9, 0x1ae4b6, 0x72fe5e, 0xc8ef34, 0xfaba39, 0xf66b19,
+                0xca2a04, 0x7a0403,
+            ],
+            ColorMap::CoolWarm => &[0x3b4cc0, 0x8db0fe, 0xdddddd, 0xf49a7b, 0xb40426],
+            ColorMap::BlueRed => &[
+                0x053061, 0x2166ac, 0x4393c3, 0x92c5de, 0xd1e5f0, 0xf7f7f7, 0xfddbc7, 0xf4a582,
+                0xd6604d, 0xb2182b, 0x67001f,
+            ],
+        }
+    }
+
+    pub fn gradient(&self) -> Gradient {
+        let colors: Vec<Rgb> = self.hex().iter().map(|&h| hex_rgb(h)).collect();
+        Gradient::even(&colors)
+    }
+}
+
+/// Colour of a `0xrrggbb` value
+pub fn hex_rgb(hex: u32) -> Rgb {
+    [hex >> 16, hex >> 8, hex].map(|c| (c & 0xff) as f32 / 255.0)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GradientError {
+    Empty,
+    /// Stop `index` is outside [0, 1] or before the one ahead of it
+    Unsorted {
+        index: usize,
+    },
+}
+
+impl fmt::Display for GradientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GradientError::Empty => write!(f, "a gradient needs at least one stop"),
+            GradientError::Unsorted { index } => {
+                write!(f, "stop {} is out of order or outside [0, 1]", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GradientError {}
+
+/// Colours at positions in [0, 1], blended linearly between them and held
+/// at the ends
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f32, Rgb)>,
+}
+
+impl Gradient {
+    /// Stops as position and colour, with positions in [0, 1] and in order
+    pub fn new(stops: Vec<(f32, Rgb)>) -> Result<Self, GradientError> {
+        if stops.is_empty() {
+            return Err(GradientError::Empty);
+        }
+        let mut last = 0.0;
+        for (index, &(pos, _)) in stops.iter().enumerate() {
+            if !(last..=1.0).contains(&pos) {
+                return Err(GradientError::Unsorted { index });
+            }
+            last = pos;
+        }
+        Ok(Self { stops })
+    }
+
+    /// The colours spread evenly from 0 to 1, black if there are none
+    pub fn even(colors: &[Rgb]) -> Self {
+        let colors = if colors.is_empty() {
+            &[[0.0; 3]]
+        } else {
+            colors
+        };
+        let last = (colors.len().max(2) - 1) as f32;
+        Self {
+            stops: colors
+                .iter()
+                .enumerate()
+                .map(|(i, &color)| (i as f32 / last, color))
+                .collect(),
+        }
+    }
+
+    pub fn stops(&self) -> &[(f32, Rgb)] {
+        &self.stops
+    }
+
+    /// Colour at `t`, which is clamped to [0, 1]
+    pub fn color(&self, t: f32) -> Rgb {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let after = self.stops.partition_point(|&(pos, _)| pos <= t);
+        if after == 0 {
+            return self.stops[0].1;
+        }
+        if after == self.stops.len() {
+            return self.stops[after - 1].1;
+        }
+        let (low, high) = (self.stops[after - 1], self.stops[after]);
+        let frac = (t - low.0) / (high.0 - low.0);
+        [0, 1, 2].map(|c| low.1[c] + frac * (high.1[c] - low.1[c]))
+    }
+
+    /// Colour of `value` placed between `min` and `max`
+    pub fn map(&self, value: f32, min: f32, max: f32) -> Rgb {
+        if max > min {
+            self.color((value - min) / (max - min))
+        } else {
+            self.color(0.5)
+        }
+    }
+}
+
+/// Relative luminance, roughly how light a colour looks
+#[cfg(test)]
+fn luminance(rgb: Rgb) -> f32 {
+    let linear = rgb.map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2]
+}
+
+#[test]
+fn test_gradient_stops() {
+    let gradient = Gradient::new(vec![
+        (0.25, [0.0, 0.0, 0.0]),
+        (0.75, [1.0, 0.5, 0.0]),
+        (1.0, [1.0, 1.0, 1.0]),
+    ])
+    .unwrap();
+    assert_eq!(gradient.color(0.0), [0.0; 3]);
+    assert_eq!(gradient.color(0.5), [0.5, 0.25, 0.0]);
+    assert_eq!(gradient.color(0.75), [1.0, 0.5, 0.0]);
+    assert_eq!(gradient.color(0.875), [1.0, 0.75, 0.5]);
+    assert_eq!(gradient.color(7.0), [1.0; 3]);
+    assert_eq!(gradient.color(f32::NAN), [0.0; 3]);
+    assert_eq!(gradient.map(5.0, 0.0, 10.0), gradient.color(0.5));
+
+    assert_eq!(Gradient::new(Vec::new()), Err(GradientError::Empty));
+    assert_eq!(
+        Gradient::new(vec![(0.5, [0.0; 3]), (0.2, [1.0; 3])]),
+        Err(GradientError::Unsorted { index: 1 })
+    );
+    let single = Gradient::even(&[[0.3; 3]]);
+    assert_eq!(single.color(0.9), [0.3; 3]);
+}
+
+#[test]
+fn test_color_maps() {
+    assert_eq!(hex_rgb(0xff8000), [1.0, 128.0 / 255.0, 0.0]);
+    for map in ColorMap::ALL {
+        let gradient = map.gradient();
+        let lightness: Vec<f32> = (0..=20)
+            .map(|i| luminance(gradient.color(i as f32 / 20.0)))
+            .collect();
+        if map.is_diverging() {
+            // dark at both ends and lightest in the middle
+            let middle = lightness[10];
+            assert!(lightness.iter().all(|&l| l <= middle + 1e-6), "{:?}", map);
+            let ends = (lightness[0] - lightness[20]).abs();
+            assert!(ends < 0.1, "{:?} {}", map, ends);
+        } else if map != ColorMap::Turbo {
+            // perceptually uniform maps only get lighter
+            assert!(
+                lightness.windows(2).all(|w| w[1] > w[0]),
+                "{:?} {:?}",
+                map,
+                lightness
+            );
+        }
+    }
+    assert_eq!(ColorMap::Viridis.gradient().color(0.0), hex_rgb(0x440154));
+    assert_eq!(ColorMap::Viridis.gradient().color(1.0), hex_rgb(0xfde725));
+}
@@ -0,0 +1,378 @@
+//! Numbers for judging how well the solver is doing
+//!
+//! After each step `FluidCube` measures how much dye there is, the
+//! energy and spin of the flow, how far a step carries it and how far it
+//! is from mass conserving, and keeps the latest measurements in a ring
+//! buffer. The sums are over the interior cells that aren't solid.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use nannou::prelude::Vec2;
+use ndarray::Array2;
+
+use crate::{
+    fluid_divergence, fluid_object::FluidCube, grid_scale, neighbour, obstacle::Mask, FluidSim,
+};
+
+/// Measurements after one step
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Sample {
+    /// Steps taken up to and including this one
+    pub step: usize,
+    /// Time at the end of the step
+    pub time: f32,
+    pub dt: f32,
+    /// Dye summed over every channel
+    pub mass: f32,
+    /// Half the sum of the squared speeds
+    pub kinetic_energy: f32,
+    /// Half the sum of the squared curl
+    pub enstrophy: f32,
+    pub max_speed: f32,
+    /// Most cells a step carries the fluid, semi-Lagrangian advection
+    /// gets blurry when this is well over one
+    pub cfl: f32,
+    /// Largest size of the divergence
+    pub divergence: f32,
+}
+
+/// The measurements that can be plotted
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    Mass,
+    KineticEnergy,
+    Enstrophy,
+    MaxSpeed,
+    Cfl,
+    Divergence,
+}
+
+impl Quantity {
+    pub const ALL: [Quantity; 6] = [
+        Quantity::Mass,
+        Quantity::KineticEnergy,
+        Quantity::Enstrophy,
+        Quantity::MaxSpeed,
+        Quantity::Cfl,
+        Quantity::Divergence,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::Mass => "mass",
+            Quantity::KineticEnergy => "kinetic energy",
+            Quantity::Enstrophy => "enstrophy",
+            Quantity::MaxSpeed => "max speed",
+            Quantity::Cfl => "cfl",
+            Quantity::Divergence => "divergence",
+        }
+    }
+
+    pub fn of(&self, sample: &Sample) -> f32 {
+        match self {
+            Quantity::Mass => sample.mass,
+            Quantity::KineticEnergy => sample.kinetic_energy,
+            Quantity::Enstrophy => sample.enstrophy,
+            Quantity::MaxSpeed => sample.max_speed,
+            Quantity::Cfl => sample.cfl,
+            Quantity::Divergence => sample.divergence,
+        }
+    }
+}
+
+/// The last `capacity` samples, oldest first
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+    steps: usize,
+    time: f32,
+}
+
+impl Default for Diagnostics {
+    /// Ten seconds at 60 frames a second
+    fn default() -> Self {
+        Self::new(600)
+    }
+}
+
+impl Diagnostics {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            steps: 0,
+            time: 0.0,
+        }
+    }
+
+    /// Add the measurements of the next step, numbering and timing it and
+    /// dropping the oldest sample when full
+    pub fn record(&mut self, sample: Sample) {
+        self.steps += 1;
+        self.time += sample.dt;
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            step: self.steps,
+            time: self.time,
+            ..sample
+        });
+    }
+
+    pub fn samples(&self) -> &VecDeque<Sample> {
+        &self.samples
+    }
+
+    pub fn latest(&self) -> Option<&Sample> {
+        self.samples.back()
+    }
+
+    /// One quantity of every sample, oldest first
+    pub fn series(&self, quantity: Quantity) -> Vec<f32> {
+        self.samples.iter().map(|s| quantity.of(s)).collect()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keep up to `capacity` samples, dropping the oldest ones if there
+    /// are more
+    pub fn set_capacity(&mut self, capacity: usize) {
+        while self.samples.len() > capacity {
+            self.samples.pop_front();
+        }
+        self.capacity = capacity;
+    }
+
+    /// Forget the samples, the step count and time carry on
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// The samples as comma separated values, a header line then one line
+    /// per sample
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "step,time,dt")?;
+        for quantity in Quantity::ALL {
+            write!(out, ",{}", quantity.name().replace(' ', "_"))?;
+        }
+        writeln!(out)?;
+        for sample in &self.samples {
+            write!(out, "{},{},{}", sample.step, sample.time, sample.dt)?;
+            for quantity in Quantity::ALL {
+                write!(out, ",{}", quantity.of(sample))?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut out)?;
+        out.flush()
+    }
+}
+
+/// Shortens the time step while the fluid is fast, so no step carries it
+/// more than `cfl` cells
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveDt {
+    pub cfl: f32,
+    /// Shortest step, so a blow up can't stall the simulation
+    pub min_dt: f32,
+}
+
+impl Default for AdaptiveDt {
+    fn default() -> Self {
+        Self {
+            cfl: 1.0,
+            min_dt: 1e-3,
+        }
+    }
+}
+
+impl AdaptiveDt {
+    /// The longest step up to `dt` keeping the CFL number of the fluid as
+    /// it is now at most `cfl`
+    pub fn dt(&self, fluid: &FluidCube, dt: f32) -> f32 {
+        let speed = fluid.max_speed();
+        let (nx, ny) = fluid.size();
+        let n = grid_scale(&[nx, ny]);
+        if speed * dt * n <= self.cfl {
+            dt
+        } else {
+            (self.cfl / (speed * n)).clamp(self.min_dt.min(dt), dt)
+        }
+    }
+}
+
+/// Largest speed of the fluid cells
+pub(crate) fn max_speed(velocity: &Array2<Vec2>, obstacles: &Mask) -> f32 {
+    fluid_cells(velocity.dim(), obstacles)
+        .map(|cell| velocity[cell].length())
+        .fold(0.0, f32::max)
+}
+
+/// Measurements of a step of `dt`, which `Diagnostics::record` numbers
+pub(crate) fn measure(
+    dyes: &[Array2<f32>],
+    velocity: &Array2<Vec2>,
+    obstacles: &Mask,
+    dt: f32,
+) -> Sample {
+    let n = grid_scale(velocity.shape());
+    let cells = || fluid_cells(velocity.dim(), obstacles);
+    let mass = dyes
+        .iter()
+        .map(|dye| cells().map(|cell| dye[cell]).sum::<f32>())
+        .sum();
+    let kinetic_energy = 0.5 * cells().map(|c| velocity[c].length_squared()).sum::<f32>();
+    let enstrophy = 0.5
+        * cells()
+            .map(|(x, y)| {
+                let at = |c| neighbour(velocity, (x, y), c, obstacles);
+                let curl = 0.5
+                    * n
+                    * (at((x + 1, y)).y - at((x - 1, y)).y - at((x, y + 1)).x + at((x, y - 1)).x);
+                curl * curl
+            })
+            .sum::<f32>();
+    let max_speed = max_speed(velocity, obstacles);
+    Sample {
+        dt,
+        mass,
+        kinetic_energy,
+        enstrophy,
+        max_speed,
+        cfl: max_speed * dt * n,
+        divergence: fluid_divergence(velocity, obstacles).max,
+        ..Sample::default()
+    }
+}
+
+/// The interior cells that aren't solid
+fn fluid_cells(dim: (usize, usize), obstacles: &Mask) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (1..dim.0 - 1)
+        .flat_map(move |x| (1..dim.1 - 1).map(move |y| (x, y)))
+        .filter(move |&(x, y)| !obstacles.is_solid(x, y))
+}
+
+#[test]
+fn test_ring_buffer_and_csv() {
+    let mut diagnostics = Diagnostics::new(3);
+    for i in 0..5 {
+        diagnostics.record(Sample {
+            dt: 0.5,
+            mass: i as f32,
+            cfl: 0.25,
+            ..Sample::default()
+        });
+    }
+    assert_eq!(diagnostics.series(Quantity::Mass), [2.0, 3.0, 4.0]);
+    let latest = diagnostics.latest().unwrap();
+    assert_eq!((latest.step, latest.time), (5, 2.5));
+
+    let mut csv = Vec::new();
+    diagnostics.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "step,time,dt,mass,kinetic_energy,enstrophy,max_speed,cfl,divergence"
+    );
+    assert_eq!(
+        lines[1..],
+        [
+            "3,1.5,0.5,2,0,0,0,0.25,0",
+            "4,2,0.5,3,0,0,0,0.25,0",
+            "5,2.5,0.5,4,0,0,0,0.25,0"
+        ]
+    );
+
+    diagnostics.set_capacity(1);
+    assert_eq!(diagnostics.series(Quantity::Mass), [4.0]);
+    diagnostics.clear();
+    diagnostics.record(Sample::default());
+    assert_eq!(diagnostics.latest().unwrap().step, 6);
+}
+
+#[test]
+fn test_measures() {
+    use nannou::prelude::vec2;
+
+    // solid body rotation, the curl is twice the rate everywhere
+    let size = (22, 22);
+    let n = 20.0;
+    let velocity = Array2::from_shape_fn(size, |(x, y)| {
+        let r = (vec2(x as f32, y as f32) - 10.5) / n;
+        vec2(-r.y, r.x)
+    });
+    let dyes = [Array2::from_elem(size, 1.0), Array2::from_elem(size, 0.5)];
+    let sample = measure(&dyes, &velocity, &Mask::new(size), 0.1);
+    assert_eq!(sample.mass, 1.5 * 400.0);
+    assert!(
+        (sample.enstrophy - 0.5 * 4.0 * 400.0).abs() < 1e-2,
+        "{}",
+        sample.enstrophy
+    );
+    let corner = (vec2(1.0, 1.0) - 10.5).length() / n;
+    assert!((sample.max_speed - corner).abs() < 1e-5);
+    assert!((sample.cfl - corner * 0.1 * n).abs() < 1e-5);
+    assert!(sample.divergence < 1e-4);
+
+    // solid cells don't count
+    let mut obstacles = Mask::new(size);
+    obstacles.fill(Vec2::ZERO, |x, y| {
+        (6..16).contains(&x) && (6..16).contains(&y)
+    });
+    let mut dye = Array2::from_elem(size, 1.0);
+    dye.slice_mut(ndarray::s![6..16, 6..16]).fill(100.0);
+    let sample = measure(&[dye], &velocity, &obstacles, 0.1);
+    assert_eq!(sample.mass, 300.0);
+    let outside = (vec2(5.0, 5.0) - 10.5).length() / n;
+    assert!((max_speed(&velocity, &obstacles) - corner).abs() < 1e-5 && corner > outside);
+}
+
+#[test]
+fn test_steps_are_recorded_and_dt_adapts() {
+    use nannou::prelude::{vec2, Rect};
+
+    let rect = Rect::from_w_h(100.0, 100.0);
+    let mut fluid = FluidCube::new((42, 42));
+    fluid.add_density(vec2(0.0, 0.0), 10.0, rect);
+    fluid.add_velocity(vec2(0.0, 0.0), vec2(2.0, 0.0), rect);
+    let adaptive = AdaptiveDt {
+        cfl: 0.5,
+        ..AdaptiveDt::default()
+    };
+    for _ in 0..10 {
+        let dt = adaptive.dt(&fluid, 1.0);
+        assert!(dt <= 1.0);
+        let before = fluid.max_speed() * dt * 40.0;
+        assert!(before <= 0.5 + 1e-5, "{}", before);
+        fluid.step(0.0, 0.0, dt, 20);
+    }
+    let samples = fluid.diagnostics().samples();
+    assert_eq!(samples.len(), 10);
+    assert_eq!(samples[9].step, 10);
+    assert!(samples[0].dt < 1.0);
+    let time: f32 = samples.iter().map(|s| s.dt).sum();
+    assert!((samples[9].time - time).abs() < 1e-5);
+    assert!(samples
+        .iter()
+        .all(|s| s.mass > 0.0 && s.kinetic_energy > 0.0));
+    // still fluid takes the full step
+    assert_eq!(adaptive.dt(&FluidCube::new((10, 10)), 0.7), 0.7);
+}
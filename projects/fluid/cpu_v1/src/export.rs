@@ -0,0 +1,501 @@
+//! Writing the fields of a fluid out for analysis elsewhere, as NumPy
+//! `.npy` arrays or as VTK image data for ParaView
+//!
+//! The fields are the dye, the velocity components, the pressure and the
+//! curl, over every cell including the boundary ring. The `.npy` arrays
+//! are indexed `[x, y]` like the solver's, with y going up, so each field
+//! goes to its own file. The VTK files hold them all as point data at the
+//! cell centres, the interior spanning [0, 1] along the longest side, and
+//! the velocity as a vector.
+
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use nannou::prelude::Vec2;
+use ndarray::Array2;
+
+use crate::{grid_scale, FluidSim};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// A `.npy` file for each field
+    #[default]
+    Npy,
+    /// One binary legacy `.vtk` file
+    VtkLegacy,
+    /// One XML `.vti` file with the data appended raw
+    VtkXml,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Npy, Format::VtkLegacy, Format::VtkXml];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Npy => "npy",
+            Format::VtkLegacy => "vtk",
+            Format::VtkXml => "vti",
+        }
+    }
+}
+
+/// Writes frames into a directory, one numbered set of files per frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exporter {
+    pub dir: PathBuf,
+    pub format: Format,
+    /// Steps between frames in a series, see `step`
+    pub every: usize,
+}
+
+impl Exporter {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            format: Format::default(),
+            every: 10,
+        }
+    }
+
+    /// Write frame `number` of the fluid, creating the directory if it's
+    /// missing. Returns the files written
+    pub fn write(&self, sim: &dyn FluidSim, number: usize) -> io::Result<Vec<PathBuf>> {
+        fs::create_dir_all(&self.dir)?;
+        match self.format {
+            Format::Npy => fields(sim)
+                .iter()
+                .map(|(name, values)| {
+                    let path = self.dir.join(format!("{}-{:05}.npy", name, number));
+                    create(&path, |out| write_npy(out, values))?;
+                    Ok(path)
+                })
+                .collect(),
+            Format::VtkLegacy => {
+                let path = self.dir.join(format!("fluid-{:05}.vtk", number));
+                create(&path, |out| write_vtk_legacy(out, sim))?;
+                Ok(vec![path])
+            }
+            Format::VtkXml => {
+                let path = self.dir.join(format!("fluid-{:05}.vti", number));
+                create(&path, |out| write_vtk_xml(out, sim))?;
+                Ok(vec![path])
+            }
+        }
+    }
+
+    /// Part of a series, writing step `step` as frame `step / every` when
+    /// it's a multiple of `every` and nothing otherwise
+    pub fn step(&self, sim: &dyn FluidSim, step: usize) -> io::Result<Vec<PathBuf>> {
+        let every = self.every.max(1);
+        if step.is_multiple_of(every) {
+            self.write(sim, step / every)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn create(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out)?;
+    out.flush()
+}
+
+/// The scalar fields by name: `density`, or `density_0` and on with more
+/// than one dye channel, then `velocity_x`, `velocity_y`, `pressure` and
+/// `curl`
+pub fn fields(sim: &dyn FluidSim) -> Vec<(String, Cow<'_, Array2<f32>>)> {
+    let channels = sim.channels();
+    let mut fields: Vec<_> = (0..channels)
+        .map(|c| {
+            let name = if channels == 1 {
+                "density".to_string()
+            } else {
+                format!("density_{}", c)
+            };
+            (name, Cow::Borrowed(sim.dye(c)))
+        })
+        .collect();
+    let velocity = sim.velocity();
+    fields.push(("velocity_x".to_string(), Cow::Owned(velocity.mapv(|v| v.x))));
+    fields.push(("velocity_y".to_string(), Cow::Owned(velocity.mapv(|v| v.y))));
+    fields.push(("pressure".to_string(), Cow::Borrowed(sim.pressure())));
+    fields.push(("curl".to_string(), Cow::Borrowed(sim.curl())));
+    fields
+}
+
+/// `values` as a version 1.0 `.npy` array of little endian `f32` with
+/// shape `(nx, ny)`
+pub fn write_npy<W: Write>(out: &mut W, values: &Array2<f32>) -> io::Result<()> {
+    let (nx, ny) = values.dim();
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        nx, ny
+    );
+    // the magic, version and length take 10 bytes, and the header is
+    // padded with spaces to a newline so the data starts 64 byte aligned
+    let len = 10 + header.len() + 1;
+    header.extend(std::iter::repeat_n(' ', (64 - len % 64) % 64));
+    header.push('\n');
+    out.write_all(b"\x93NUMPY\x01\x00")?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    for v in values.iter() {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad npy file: {}", msg))
+}
+
+/// A 2D array of little endian `f32` from a `.npy` file, as written by
+/// `write_npy` or by NumPy
+pub fn read_npy<R: Read>(input: &mut R) -> io::Result<Array2<f32>> {
+    let mut start = [0u8; 8];
+    input.read_exact(&mut start)?;
+    if &start[..6] != b"\x93NUMPY" {
+        return Err(invalid("no magic"));
+    }
+    let len = match start[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            input.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            input.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        _ => return Err(invalid("unknown version")),
+    };
+    let mut header = vec![0u8; len];
+    input.read_exact(&mut header)?;
+    let header = String::from_utf8(header).map_err(|_| invalid("header isn't text"))?;
+    if header_value(&header, "descr") != Some("'<f4'") {
+        return Err(invalid("not little endian f32"));
+    }
+    if header_value(&header, "fortran_order") != Some("False") {
+        return Err(invalid("not in C order"));
+    }
+    let shape: Vec<usize> = header_value(&header, "shape")
+        .and_then(|shape| shape.strip_prefix('(')?.strip_suffix(')'))
+        .ok_or_else(|| invalid("no shape"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| invalid("bad shape")))
+        .collect::<io::Result<_>>()?;
+    let (nx, ny) = match shape[..] {
+        [nx, ny] => (nx, ny),
+        _ => return Err(invalid("not 2D")),
+    };
+    let bytes = nx
+        .checked_mul(ny)
+        .and_then(|cells| cells.checked_mul(4))
+        .ok_or_else(|| invalid("bad shape"))?;
+    // read only as far as the file goes, a huge shape in a short file
+    // mustn't allocate it all first
+    let mut data = Vec::new();
+    input.take(bytes as u64).read_to_end(&mut data)?;
+    if data.len() != bytes {
+        return Err(invalid("less data than the shape"));
+    }
+    let values = data
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Array2::from_shape_vec((nx, ny), values).map_err(|_| invalid("bad shape"))
+}
+
+/// The text of the value of `key` in a `.npy` header dictionary, which
+/// only holds strings, booleans and tuples
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let rest = &header[header.find(&format!("'{}'", key))? + key.len() + 2..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim_end())
+}
+
+/// Cells along each axis and the spacing and origin of the cell centres,
+/// with the interior spanning [0, 1] along the longest side
+fn grid(sim: &dyn FluidSim) -> ((usize, usize), f32, f32) {
+    let (nx, ny) = sim.size();
+    let h = 1.0 / grid_scale(&[nx, ny]);
+    ((nx, ny), h, -0.5 * h)
+}
+
+/// Values in VTK's order, x changing fastest
+fn vtk_order<A: Copy>(values: &Array2<A>) -> impl Iterator<Item = A> + '_ {
+    values.t().into_iter().copied()
+}
+
+fn vectors(velocity: &Array2<Vec2>) -> impl Iterator<Item = f32> + '_ {
+    vtk_order(velocity).flat_map(|v| [v.x, v.y, 0.0])
+}
+
+/// The fields as structured points in a binary legacy VTK file, which is
+/// big endian
+pub fn write_vtk_legacy<W: Write>(out: &mut W, sim: &dyn FluidSim) -> io::Result<()> {
+    let ((nx, ny), h, origin) = grid(sim);
+    writeln!(out, "# vtk DataFile Version 3.0")?;
+    writeln!(out, "fluid")?;
+    writeln!(out, "BINARY")?;
+    writeln!(out, "DATASET STRUCTURED_POINTS")?;
+    writeln!(out, "DIMENSIONS {} {} 1", nx, ny)?;
+    writeln!(out, "ORIGIN {} {} 0", origin, origin)?;
+    writeln!(out, "SPACING {} {} {}", h, h, h)?;
+    writeln!(out, "POINT_DATA {}", nx * ny)?;
+    for (name, values) in fields(sim) {
+        if name.starts_with("velocity") {
+            continue;
+        }
+        writeln!(out, "SCALARS {} float 1", name)?;
+        writeln!(out, "LOOKUP_TABLE default")?;
+        for v in vtk_order(&values) {
+            out.write_all(&v.to_be_bytes())?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "VECTORS velocity float")?;
+    for v in vectors(sim.velocity()) {
+        out.write_all(&v.to_be_bytes())?;
+    }
+    writeln!(out)
+}
+
+/// The fields as XML VTK image data, the arrays appended raw after the
+/// XML, each after its length in bytes as a `u32`
+pub fn write_vtk_xml<W: Write>(out: &mut W, sim: &dyn FluidSim) -> io::Result<()> {
+    let ((nx, ny), h, origin) = grid(sim);
+    let scalars: Vec<_> = fields(sim)
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with("velocity"))
+        .collect();
+    let extent = format!("0 {} 0 {} 0 0", nx - 1, ny - 1);
+    let scalar_bytes = 4 * nx * ny;
+    writeln!(out, r#"<?xml version="1.0"?>"#)?;
+    writeln!(
+        out,
+        r#"<VTKFile type="ImageData" version="1.0" byte_order="LittleEndian" header_type="UInt32">"#
+    )?;
+    writeln!(
+        out,
+        r#"  <ImageData WholeExtent="{}" Origin="{} {} 0" Spacing="{} {} {}">"#,
+        extent, origin, origin, h, h, h
+    )?;
+    writeln!(out, r#"    <Piece Extent="{}">"#, extent)?;
+    writeln!(
+        out,
+        r#"      <PointData Scalars="{}" Vectors="velocity">"#,
+        scalars[0].0
+    )?;
+    let mut offset = 0;
+    for (name, _) in &scalars {
+        writeln!(
+            out,
+            r#"        <DataArray type="Float32" Name="{}" format="appended" offset="{}"/>"#,
+            name, offset
+        )?;
+        offset += 4 + scalar_bytes;
+    }
+    writeln!(
+        out,
+        r#"        <DataArray type="Float32" Name="velocity" NumberOfComponents="3" format="appended" offset="{}"/>"#,
+        offset
+    )?;
+    writeln!(out, "      </PointData>")?;
+    writeln!(out, "    </Piece>")?;
+    writeln!(out, "  </ImageData>")?;
+    write!(out, r#"  <AppendedData encoding="raw">"#)?;
+    write!(out, "\n   _")?;
+    for (_, values) in &scalars {
+        out.write_all(&(scalar_bytes as u32).to_le_bytes())?;
+        for v in vtk_order(values) {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    out.write_all(&(3 * scalar_bytes as u32).to_le_bytes())?;
+    for v in vectors(sim.velocity()) {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    writeln!(out, "\n  </AppendedData>")?;
+    writeln!(out, "</VTKFile>")
+}
+
+#[test]
+fn test_npy_round_trip() {
+    let values = Array2::from_shape_fn((7, 5), |(x, y)| x as f32 - 0.25 * y as f32);
+    let mut bytes = Vec::new();
+    write_npy(&mut bytes, &values).unwrap();
+
+    assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+    let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert_eq!((10 + len) % 64, 0);
+    let header = std::str::from_utf8(&bytes[10..10 + len]).unwrap();
+    assert!(header.ends_with('\n'));
+    assert_eq!(header_value(header, "descr"), Some("'<f4'"));
+    assert_eq!(header_value(header, "fortran_order"), Some("False"));
+    assert_eq!(header_value(header, "shape"), Some("(7, 5)"));
+    assert_eq!(bytes.len(), 10 + len + 4 * 35);
+    // C order, y changing fastest
+    assert_eq!(
+        &bytes[10 + len + 4..10 + len + 8],
+        &(-0.25f32).to_le_bytes()
+    );
+
+    assert_eq!(read_npy(&mut &bytes[..]).unwrap(), values);
+
+    // as NumPy writes it, without the trailing comma
+    let mut numpy = b"\x93NUMPY\x01\x00".to_vec();
+    let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (1, 2)}\n";
+    numpy.extend((header.len() as u16).to_le_bytes());
+    numpy.extend(header.as_bytes());
+    numpy.extend(1f32.to_le_bytes());
+    numpy.extend(2f32.to_le_bytes());
+    let read = read_npy(&mut &numpy[..]).unwrap();
+    assert_eq!(read, ndarray::arr2(&[[1.0, 2.0]]));
+
+    for bad in [
+        header.replace("<f4", "<f8"),
+        header.replace("False", "True"),
+        header.replace("(1, 2)", "(2,)"),
+        // too big to allocate, rather than overflowing
+        header.replace("(1, 2)", &format!("({}, 2)", usize::MAX)),
+        // fits, but is far more than the file holds
+        header.replace("(1, 2)", "(1099511627776, 1)"),
+    ] {
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((bad.len() as u16).to_le_bytes());
+        bytes.extend(bad.as_bytes());
+        bytes.extend([0; 8]);
+        let error = read_npy(&mut &bytes[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", bad);
+    }
+    // cut short
+    assert!(read_npy(&mut &numpy[..numpy.len() - 1]).is_err());
+}
+
+#[test]
+fn test_vtk_legacy() {
+    let fluid = crate::stirred_cube((7, 5), 1);
+    let mut bytes = Vec::new();
+    write_vtk_legacy(&mut bytes, &fluid).unwrap();
+
+    let text = String::from_utf8_lossy(&bytes);
+    assert!(text.starts_with("# vtk DataFile Version 3.0\nfluid\nBINARY\n"));
+    assert!(text.contains("DIMENSIONS 7 5 1\n"));
+    assert!(text.contains("SPACING 0.2 0.2 0.2\n"));
+    assert!(text.contains("POINT_DATA 35\n"));
+    // the pressure follows its header, x changing fastest
+    let header = b"SCALARS pressure float 1\nLOOKUP_TABLE default\n";
+    let start = bytes
+        .windows(header.len())
+        .position(|w| w == header)
+        .unwrap()
+        + header.len();
+    let pressure: Vec<f32> = bytes[start..start + 4 * 35]
+        .chunks_exact(4)
+        .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    let expected: Vec<f32> = fluid.pressure().t().iter().copied().collect();
+    assert_eq!(pressure, expected);
+    assert!(pressure.iter().any(|&p| p != 0.0));
+    for name in ["density", "curl"] {
+        assert!(text.contains(&format!("SCALARS {} float 1\n", name)));
+    }
+    assert!(text.contains("VECTORS velocity float\n"));
+    assert!(bytes.ends_with(b"\n"));
+}
+
+#[test]
+fn test_vtk_xml() {
+    let fluid = crate::stirred_cube((7, 5), 3);
+    let mut bytes = Vec::new();
+    write_vtk_xml(&mut bytes, &fluid).unwrap();
+
+    let marker = b"<AppendedData encoding=\"raw\">\n   _";
+    let start = bytes
+        .windows(marker.len())
+        .position(|w| w == marker)
+        .unwrap()
+        + marker.len();
+    let text = std::str::from_utf8(&bytes[..start]).unwrap();
+    assert!(text.contains(r#"WholeExtent="0 6 0 4 0 0""#));
+    // every array is where its offset says, after its length
+    let names = ["density_0", "density_1", "density_2", "pressure", "curl"];
+    for (i, name) in names.iter().chain(&["velocity"]).enumerate() {
+        let tag = format!(r#"Name="{}""#, name);
+        let after = &text[text.find(&tag).unwrap()..];
+        let after = &after[after.find(r#"offset=""#).unwrap() + 8..];
+        let offset: usize = after[..after.find('"').unwrap()].parse().unwrap();
+        assert_eq!(offset, i * (4 + 4 * 35), "{}", name);
+        let at = &bytes[start + offset..];
+        let len = u32::from_le_bytes([at[0], at[1], at[2], at[3]]) as usize;
+        let values: Vec<f32> = at[4..4 + len]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let expected: Vec<f32> = match *name {
+            "velocity" => vectors(fluid.velocity()).collect(),
+            "pressure" => vtk_order(fluid.pressure()).collect(),
+            "curl" => vtk_order(fluid.curl()).collect(),
+            _ => vtk_order(fluid.dye(i)).collect(),
+        };
+        assert_eq!(values, expected, "{}", name);
+    }
+    let end = start + 6 * 4 + 8 * 4 * 35;
+    assert_eq!(&bytes[end..], b"\n  </AppendedData>\n</VTKFile>\n");
+}
+
+#[test]
+fn test_exporter_series() {
+    let dir = std::env::temp_dir().join(format!("cpu_v1-export-{}", std::process::id()));
+    let fluid = crate::stirred_cube((7, 5), 1);
+    let mut exporter = Exporter::new(&dir);
+    exporter.every = 3;
+
+    let mut written = Vec::new();
+    for step in 0..7 {
+        written.extend(exporter.step(&fluid, step).unwrap());
+    }
+    let names: Vec<_> = written
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names.len(), 3 * 5);
+    assert_eq!(
+        names[..5],
+        [
+            "density-00000.npy",
+            "velocity_x-00000.npy",
+            "velocity_y-00000.npy",
+            "pressure-00000.npy",
+            "curl-00000.npy"
+        ]
+    );
+    assert_eq!(names[14], "curl-00002.npy");
+    let velocity_y = read_npy(&mut File::open(&written[2]).unwrap()).unwrap();
+    assert_eq!(velocity_y, fluid.velocity().mapv(|v| v.y));
+
+    for format in [Format::VtkLegacy, Format::VtkXml] {
+        exporter.format = format;
+        let path = exporter.write(&fluid, 12).unwrap();
+        assert_eq!(path, [dir.join(format!("fluid-00012.{}", format.name()))]);
+        assert!(fs::metadata(&path[0]).unwrap().len() > 8 * 35);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,6 +1,39 @@
-use cpu_v1::fluid_object::{DensColor, FluidCube};
-use nannou::prelude::*;
-use nannou_egui::{self, egui, Egui};
+use std::path::PathBuf;
+
+use cpu_v1::{
+    advection::Advection,
+    boundary::{Boundaries, Boundary},
+    buoyancy::Buoyancy,
+    checkpoint::{Checkpoint, CheckpointError},
+    colormap::{ColorMap, Gradient},
+    diagnostics::{AdaptiveDt, Quantity},
+    export::{Exporter, Format},
+    fluid_object::{DensColor, DyeDisplay, FluidCube, SplatColor},
+    mac::MacCube,
+    obstacle::{Obstacle, Shape},
+    particles::{Integrator, Interpolation, ParticleStyle, Particles},
+    pressure::PressureSolver,
+    render::{FluidRender, Scalar},
+    splat::{Brush, FluidStroke},
+    FluidSim,
+};
+use nannou::{
+    image::{self, RgbaImage},
+    prelude::*,
+};
+use nannou_egui::{
+    self,
+    egui::{
+        self,
+        plot::{Line, Plot, Values},
+    },
+    Egui,
+};
+use ndarray::Array2;
+use vector_field::{
+    lic::{white_noise, Kernel, Lic},
+    streamlines::Streamlines,
+};
 
 fn main() {
     nannou::app(model).update(update).run();
@@ -10,13 +43,75 @@ struct Settings {
     scale: f32,
     dens_opt: DensOpt,
     vel_opt: VelOpt,
+    background: Background,
+    /// Scalar shown when the background is a colour map
+    scalar: Scalar,
+    /// Colour map options for each scalar, indexed by `Scalar as usize`
+    maps: [MapOpt; 4],
+    /// Evenly spaced stops of the custom colour map
+    custom_colors: [[f32; 3]; 3],
+    /// Streaks of the LIC background, in pixels of the noise
+    lic: Lic,
+    /// Evenly spaced streamlines over the background, in cells
+    streamlines: Streamlines,
+    draw_streamlines: bool,
+    /// Longest time step, the one taken unless `adaptive_dt` shortens it
     dt: f32,
+    adaptive_dt: bool,
+    adaptive: AdaptiveDt,
     iter: usize,
+    parallel: bool,
+    /// Run the staggered MAC grid instead, which has no edge modes,
+    /// obstacles, advection schemes, vorticity confinement or heat
+    staggered: bool,
+    advection: Advection,
+    pressure: PressureSolver,
+    /// Stop the pressure solve early once it reaches `tolerance`, rather
+    /// than running every sweep
+    early_stop: bool,
+    /// Pressure residual to stop at, relative to the starting one
+    tolerance: f32,
+    /// Strength of the vorticity confinement, zero turns it off
+    vorticity: f32,
+    /// Fractions of the velocity and density lost per unit time
+    velocity_dissipation: f32,
+    density_dissipation: f32,
+    buoyancy: Buoyancy,
+    /// Keep a patch near the bottom of the window `heat_temperature` above
+    /// the ambient, giving off smoke like a fire
+    heat_source: bool,
+    heat_temperature: f32,
+    heat_brush: Brush,
+    boundaries: Boundaries,
+    obstacle: bool,
+    obstacle_radius: f32,
+    /// Painting with the mouse, left drag when there's no obstacle to drag
+    /// and right drag always
+    brush: Brush,
+    particle_opt: ParticleOpt,
+    /// Black and white image of solid areas, from the first argument
+    mask_path: Option<PathBuf>,
+    export: Exporter,
+    /// Write every `export.every`th step
+    export_series: bool,
 }
 struct Model {
     fluid: FluidCube,
+    /// Stepped and drawn instead of `fluid` when the grid is staggered
+    mac: Option<MacCube>,
+    /// Tracers carried along by whichever solver is running
+    particles: Particles,
+    /// The background image, the size of the window
+    texture: wgpu::Texture,
+    /// Smeared along the flow for the LIC background, at half the
+    /// window's resolution
+    noise: Array2<f32>,
     egui: Egui,
     settings: Settings,
+    /// Mouse position last update, to move the obstacle by
+    mouse: Vec2,
+    /// Steps taken, numbering the exported frames
+    steps: usize,
 }
 
 const SIZE: usize = 1000;
@@ -31,21 +126,253 @@ fn key_pressed(app: &App, model: &mut Model, key: Key) {
     match key {
         Key::D => model.settings.dens_opt.draw_dens = !model.settings.dens_opt.draw_dens,
         Key::V => model.settings.vel_opt.draw_vel = !model.settings.vel_opt.draw_vel,
+        Key::I => save_field_image(app, model),
+        Key::K => save_checkpoint(app, model),
+        Key::L => load_checkpoint(app, model),
+        Key::E => {
+            let sim = active(&mut model.fluid, &mut model.mac);
+            let written = model.settings.export.write(sim, model.steps);
+            report_export(&model.settings.export, written);
+        }
+        Key::P => {
+            model.settings.particle_opt.draw_particles = !model.settings.particle_opt.draw_particles
+        }
         _other_key => {}
     }
 }
 
+/// What's drawn under the velocity lines and particles
+fn field_image(model: &Model, size: (u32, u32)) -> RgbaImage {
+    let sim: &dyn FluidSim = match &model.mac {
+        Some(mac) => mac,
+        None => &model.fluid,
+    };
+    let settings = &model.settings;
+    match settings.background {
+        Background::Dye => sim.render_dens(size, settings.dens_opt.display()),
+        Background::Velocity => sim.render_vel(size, settings.vel_opt.max_speed),
+        Background::Lic => sim.render_lic(
+            &settings.lic,
+            &model.noise,
+            settings.vel_opt.max_speed,
+            size,
+        ),
+        Background::Scalar => {
+            let scalar = settings.scalar;
+            let map = &settings.maps[scalar as usize];
+            let gradient = if map.custom {
+                Gradient::even(&settings.custom_colors)
+            } else {
+                map.map.gradient()
+            };
+            let range = map.fixed.then(|| {
+                if scalar.is_signed() {
+                    (-map.max, map.max)
+                } else {
+                    (0.0, map.max)
+                }
+            });
+            sim.render_scalar(scalar, &gradient, range, size)
+        }
+    }
+}
+
+/// Save the field image at the window's size next to the captured frames,
+/// without the velocity lines, particles or egui
+fn save_field_image(app: &App, model: &Model) {
+    let rect = app.window_rect();
+    let image = field_image(model, (rect.w() as u32, rect.h() as u32));
+    let path = interaction::frame_path(app)
+        .with_file_name(format!("field-{:03}.png", app.elapsed_frames()));
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .map_err(image::ImageError::from)
+        .and_then(|_| image.save(&path));
+    if let Err(e) = saved {
+        eprintln!("can't save {}: {}", path.display(), e);
+    }
+}
+
+fn checkpoint_path(app: &App) -> PathBuf {
+    app.assets_path()
+        .expect("Expected project path")
+        .join("checkpoints")
+        .join("fluid.ckp")
+}
+
+/// Save the collocated grid. The staggered one has no checkpoint, so
+/// nothing is saved while it is running rather than a stale grid.
+fn save_checkpoint(app: &App, model: &Model) {
+    let path = checkpoint_path(app);
+    if model.mac.is_some() {
+        eprintln!(
+            "can't save {}: only the collocated grid has checkpoints, turn off staggered first",
+            path.display()
+        );
+        return;
+    }
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .map_err(CheckpointError::from)
+        .and_then(|_| model.fluid.checkpoint().save(&path));
+    if let Err(e) = saved {
+        eprintln!("can't save {}: {}", path.display(), e);
+    }
+}
+
+/// Restore the saved checkpoint at the current window size, and take the
+/// settings it was saved with. The checkpoint is of the collocated grid,
+/// so the staggered one is turned off to show it.
+fn load_checkpoint(app: &App, model: &mut Model) {
+    let path = checkpoint_path(app);
+    let checkpoint = match Checkpoint::load(&path) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            eprintln!("can't load {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let rect = app.window_rect();
+    let settings = &mut model.settings;
+    let fluid = FluidCube::restore(checkpoint, scaled_fluid_cube(settings.scale, rect), rect);
+    settings.dens_opt.rgb = fluid.channels() == 3;
+    settings.advection = fluid.advection();
+    settings.pressure = fluid.pressure_solver();
+    settings.early_stop = fluid.tolerance() > 0.0;
+    if settings.early_stop {
+        settings.tolerance = fluid.tolerance();
+    }
+    settings.parallel = fluid.parallel();
+    settings.boundaries = fluid.boundaries();
+    settings.vorticity = fluid.vorticity();
+    (settings.velocity_dissipation, settings.density_dissipation) = fluid.dissipation();
+    settings.buoyancy = fluid.buoyancy();
+    // the circle dragged around is obstacle 0, see `place_obstacles`
+    settings.obstacle = false;
+    if let Some(Obstacle {
+        shape: Shape::Circle { radius, .. },
+        ..
+    }) = fluid.obstacles().first()
+    {
+        settings.obstacle = true;
+        settings.obstacle_radius = *radius;
+    }
+    if !fluid.has_obstacle_image() {
+        settings.mask_path = None;
+    }
+    settings.staggered = false;
+    model.mac = None;
+    model.fluid = fluid;
+}
+
+fn report_export(exporter: &Exporter, written: std::io::Result<Vec<PathBuf>>) {
+    if let Err(e) = written {
+        eprintln!("can't export to {}: {}", exporter.dir.display(), e);
+    }
+}
+
+fn save_diagnostics(app: &App, fluid: &FluidCube) {
+    let path =
+        interaction::save_path(app).join(format!("diagnostics-{:03}.csv", app.elapsed_frames()));
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| fluid.diagnostics().save_csv(&path));
+    if let Err(e) = saved {
+        eprintln!("can't save {}: {}", path.display(), e);
+    }
+}
+
+fn field_texture(app: &App, rect: Rect) -> wgpu::Texture {
+    wgpu::TextureBuilder::new()
+        .size([rect.w() as u32, rect.h() as u32])
+        .format(wgpu::TextureFormat::Rgba8Unorm)
+        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build(app.main_window().device())
+}
+
+fn lic_noise(rect: Rect) -> Array2<f32> {
+    white_noise((rect.w() as usize / 2, rect.h() as usize / 2), 0)
+}
+
 fn scaled_fluid_cube(scale: f32, rect: Rect) -> (usize, usize) {
     let wh = scale * rect.wh();
     (wh.x.floor() as usize, wh.y.floor() as usize)
 }
 
-fn regen(scale: f32, rect: Rect) -> FluidCube {
-    FluidCube::new(scaled_fluid_cube(scale, rect))
+fn regen(settings: &Settings, rect: Rect) -> FluidCube {
+    let mut fluid =
+        FluidCube::with_boundaries(scaled_fluid_cube(settings.scale, rect), settings.boundaries);
+    fluid.set_channels(settings.dens_opt.channels());
+    place_obstacles(&mut fluid, settings, rect);
+    fluid
+}
+
+fn regen_mac(settings: &Settings, rect: Rect) -> Option<MacCube> {
+    settings.staggered.then(|| {
+        let mut mac = MacCube::new(scaled_fluid_cube(settings.scale, rect));
+        mac.set_channels(settings.dens_opt.channels());
+        mac
+    })
+}
+
+/// The solver being stepped and drawn
+fn active<'a>(fluid: &'a mut FluidCube, mac: &'a mut Option<MacCube>) -> &'a mut dyn FluidSim {
+    match mac {
+        Some(mac) => mac,
+        None => fluid,
+    }
+}
+
+/// The image mask if there is one, and the circle dragged around with the
+/// mouse if it's on. The circle is always obstacle 0.
+fn place_obstacles(fluid: &mut FluidCube, settings: &Settings, rect: Rect) {
+    fluid.clear_obstacles();
+    if settings.obstacle {
+        let circle = Shape::Circle {
+            centre: rect.xy() - vec2(rect.w() / 4.0, 0.0),
+            radius: settings.obstacle_radius,
+        };
+        fluid.add_obstacle(Obstacle::new(circle), rect);
+    }
+    if let Some(path) = &settings.mask_path {
+        if let Err(e) = fluid.load_obstacle_image(path, rect) {
+            eprintln!("can't load obstacle mask {}: {}", path.display(), e);
+        }
+    }
 }
 
 fn resized(app: &App, model: &mut Model, _vec: Vec2) {
-    model.fluid = regen(model.settings.scale, app.window_rect());
+    let rect = app.window_rect();
+    let size = scaled_fluid_cube(model.settings.scale, rect);
+    model.fluid = model.fluid.resized(size, rect);
+    model.mac = model.mac.as_ref().map(|mac| mac.resized(size));
+    model.texture = field_texture(app, app.window_rect());
+    model.noise = lic_noise(app.window_rect());
+}
+
+/// Radio buttons choosing the mode of one edge. Periodicity is per axis,
+/// so picking periodic also wraps the `opposite` edge, and leaving it
+/// gives the opposite edge the same new mode
+fn boundary_choice(
+    ui: &mut egui::Ui,
+    label: &str,
+    boundary: &mut Boundary,
+    opposite: &mut Boundary,
+) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label(label);
+        for mode in Boundary::ALL {
+            changed |= ui.radio_value(boundary, mode, mode.name()).changed();
+        }
+    });
+    if changed && (*boundary == Boundary::Periodic || *opposite == Boundary::Periodic) {
+        *opposite = *boundary;
+    }
+    changed
 }
 
 fn model(app: &App) -> Model {
@@ -63,10 +390,11 @@ fn model(app: &App) -> Model {
     let scale = 0.25;
     let rect = app.window_rect();
 
-    let fluid = FluidCube::new(scaled_fluid_cube(scale, rect));
     let dens_opt = DensOpt {
         draw_dens: true,
         dens_color: DensColor::new(0.5, 0.5),
+        rgb: false,
+        colorful: false,
         visc: 0.001,
         input_amount: 3.0,
     };
@@ -74,8 +402,9 @@ fn model(app: &App) -> Model {
         diff: 0.001,
         line_length: 5.0,
         color: hsv(1.0, 1.0, 0.5),
-        draw_vel: true,
+        draw_vel: false,
         input_amount: 5.0,
+        max_speed: 1.0,
     };
     let window = app.window(window_id).unwrap();
     let egui = Egui::from_window(&window);
@@ -83,13 +412,67 @@ fn model(app: &App) -> Model {
         scale,
         vel_opt,
         dens_opt,
+        background: Background::Dye,
+        scalar: Scalar::Density,
+        maps: [
+            MapOpt::new(ColorMap::Viridis, 1.0),
+            MapOpt::new(ColorMap::Inferno, 1.0),
+            MapOpt::new(ColorMap::CoolWarm, 0.1),
+            MapOpt::new(ColorMap::BlueRed, 0.01),
+        ],
+        custom_colors: [[0.0, 0.0, 0.2], [0.9, 0.3, 0.1], [1.0, 1.0, 0.8]],
+        lic: Lic::default(),
+        streamlines: Streamlines::default(),
+        draw_streamlines: false,
         dt: 1.0,
+        adaptive_dt: false,
+        adaptive: AdaptiveDt::default(),
         iter: 4,
+        parallel: true,
+        staggered: false,
+        advection: Advection::default(),
+        pressure: PressureSolver::default(),
+        early_stop: true,
+        tolerance: 1e-3,
+        vorticity: 0.0,
+        velocity_dissipation: 0.0,
+        density_dissipation: 0.0,
+        buoyancy: Buoyancy::default(),
+        heat_source: false,
+        heat_temperature: 50.0,
+        heat_brush: Brush {
+            radius: 60.0,
+            ..Brush::default()
+        },
+        boundaries: Boundaries::default(),
+        obstacle: false,
+        obstacle_radius: 60.0,
+        brush: Brush::default(),
+        particle_opt: ParticleOpt {
+            draw_particles: true,
+            style: ParticleStyle::default(),
+            size: 2.0,
+            color: hsv(0.6, 0.3, 1.0),
+            paint: false,
+            per_frame: 50,
+            spacing: 10.0,
+        },
+        mask_path: std::env::args().nth(1).map(PathBuf::from),
+        export: Exporter::new(interaction::save_path(app).join("export")),
+        export_series: false,
     };
+    let fluid = regen(&settings, rect);
+    let mac = regen_mac(&settings, rect);
     Model {
         fluid,
+        mac,
+        particles: Particles::new(50_000),
+        texture: field_texture(app, rect),
+        noise: lic_noise(rect),
         egui,
         settings,
+        mouse: Vec2::ZERO,
+        steps: 0,
     }
 }
 
@@ -102,6 +485,8 @@ fn update(app: &App, model: &mut Model, update: Update) {
         ref mut egui,
         ref mut settings,
         ref mut fluid,
+        ref mut mac,
+        ref mut particles,
         ..
     } = *model;
 
@@ -122,6 +507,81 @@ fn update(app: &App, model: &mut Model, update: Update) {
             .changed();
         nannou_egui::edit_color(ui, &mut settings.vel_opt.color);
 
+        //background
+        ui.horizontal(|ui| {
+            ui.label("background");
+            for background in Background::ALL {
+                ui.radio_value(&mut settings.background, background, background.name());
+            }
+        });
+        match settings.background {
+            Background::Dye => {}
+            Background::Velocity | Background::Lic => {
+                ui.add(
+                    egui::Slider::new(&mut settings.vel_opt.max_speed, 0.01..=10.0)
+                        .logarithmic(true)
+                        .text("image max speed"),
+                )
+                .changed();
+                if settings.background == Background::Lic {
+                    ui.add(
+                        egui::Slider::new(&mut settings.lic.length, 1.0..=50.0).text("lic length"),
+                    )
+                    .changed();
+                    ui.horizontal(|ui| {
+                        ui.label("lic kernel");
+                        for kernel in Kernel::ALL {
+                            ui.radio_value(&mut settings.lic.kernel, kernel, kernel.name());
+                        }
+                    });
+                }
+            }
+            Background::Scalar => {
+                ui.horizontal(|ui| {
+                    ui.label("scalar");
+                    for scalar in Scalar::ALL {
+                        ui.radio_value(&mut settings.scalar, scalar, scalar.name());
+                    }
+                });
+                let map = &mut settings.maps[settings.scalar as usize];
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("color map");
+                    for color_map in ColorMap::ALL {
+                        let selected = !map.custom && map.map == color_map;
+                        if ui.radio(selected, color_map.name()).clicked() {
+                            map.map = color_map;
+                            map.custom = false;
+                        }
+                    }
+                    ui.radio_value(&mut map.custom, true, "custom");
+                });
+                if map.custom {
+                    ui.horizontal(|ui| {
+                        for color in &mut settings.custom_colors {
+                            ui.color_edit_button_rgb(color);
+                        }
+                    });
+                }
+                ui.checkbox(&mut map.fixed, "fixed range");
+                if map.fixed {
+                    ui.add(
+                        egui::Slider::new(&mut map.max, 1e-4..=100.0)
+                            .logarithmic(true)
+                            .text("range max"),
+                    )
+                    .changed();
+                }
+            }
+        }
+
+        //streamlines
+        ui.checkbox(&mut settings.draw_streamlines, "streamlines");
+        ui.add(
+            egui::Slider::new(&mut settings.streamlines.separation, 1.0..=20.0)
+                .text("streamline separation"),
+        )
+        .changed();
+
         //dens
         ui.add(egui::Slider::new(&mut settings.dens_opt.visc, 0.0..=1.0).text("dens visc"))
             .changed();
@@ -137,11 +597,84 @@ fn update(app: &App, model: &mut Model, update: Update) {
             egui::Slider::new(&mut settings.dens_opt.dens_color.sat, 0.0..=1.0).text("dens sat"),
         )
         .changed();
+        if ui.checkbox(&mut settings.dens_opt.rgb, "rgb dye").changed() {
+            fluid.set_channels(settings.dens_opt.channels());
+            if let Some(mac) = mac {
+                mac.set_channels(settings.dens_opt.channels());
+            }
+        }
+        ui.checkbox(&mut settings.dens_opt.colorful, "colorful");
 
         //all
         ui.add(egui::Slider::new(&mut settings.dt, 0.0..=5.0).text("time step"))
             .changed();
-        ui.add(egui::Slider::new(&mut settings.iter, 1..=20).text("iter"))
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.adaptive_dt, "adaptive");
+            ui.add(egui::Slider::new(&mut settings.adaptive.cfl, 0.1..=5.0).text("max cfl"));
+        });
+        ui.add(egui::Slider::new(&mut settings.iter, 1..=100).text("max iter"))
+            .changed();
+        ui.checkbox(&mut settings.parallel, "parallel");
+        if ui
+            .checkbox(&mut settings.staggered, "staggered (MAC) grid")
+            .changed()
+        {
+            *mac = regen_mac(settings, rect);
+        }
+        ui.horizontal(|ui| {
+            ui.label("advection");
+            for scheme in Advection::ALL {
+                ui.radio_value(&mut settings.advection, scheme, scheme.name());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("pressure");
+            for method in PressureSolver::ALL {
+                ui.radio_value(&mut settings.pressure, method, method.name());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.early_stop, "stop early");
+            if settings.early_stop {
+                ui.add(
+                    egui::Slider::new(&mut settings.tolerance, 1e-6..=1e-1)
+                        .logarithmic(true)
+                        .text("tolerance"),
+                );
+            }
+        });
+        let stats = active(fluid, mac).pressure_stats();
+        ui.label(format!(
+            "pressure {} iterations, residual {:.1e}, {:.2} ms",
+            stats.iterations,
+            stats.residual,
+            stats.time.as_secs_f64() * 1000.0
+        ));
+        ui.add(egui::Slider::new(&mut settings.vorticity, 0.0..=50.0).text("vorticity"))
+            .changed();
+        ui.add(
+            egui::Slider::new(&mut settings.velocity_dissipation, 0.0..=1.0)
+                .text("vel dissipation"),
+        )
+        .changed();
+        ui.add(
+            egui::Slider::new(&mut settings.density_dissipation, 0.0..=1.0)
+                .text("dens dissipation"),
+        )
+        .changed();
+        let buoyancy = &mut settings.buoyancy;
+        ui.add(egui::Slider::new(&mut buoyancy.lift, 0.0..=1.0).text("heat lift"))
+            .changed();
+        ui.add(egui::Slider::new(&mut buoyancy.weight, 0.0..=10.0).text("smoke weight"))
+            .changed();
+        ui.add(egui::Slider::new(&mut buoyancy.ambient, 0.0..=100.0).text("ambient temp"))
+            .changed();
+        ui.add(egui::Slider::new(&mut buoyancy.cooling, 0.0..=2.0).text("cooling"))
+            .changed();
+        ui.checkbox(&mut settings.heat_source, "heat source");
+        ui.add(egui::Slider::new(&mut settings.heat_temperature, 0.0..=200.0).text("heat temp"))
+            .changed();
+        ui.add(egui::Slider::new(&mut settings.heat_brush.radius, 1.0..=200.0).text("heat radius"))
             .changed();
         let mut scale_changed = false;
         scale_changed |= ui
@@ -149,50 +682,346 @@ fn update(app: &App, model: &mut Model, update: Update) {
             .changed();
         scale_changed |= ui.button("Generate").clicked();
         if scale_changed {
-            *fluid = regen(settings.scale, rect);
+            *fluid = regen(settings, rect);
+            *mac = regen_mac(settings, rect);
         }
+
+        let boundaries = &mut settings.boundaries;
+        let mut boundaries_changed = false;
+        let Boundaries {
+            left,
+            right,
+            bottom,
+            top,
+        } = boundaries;
+        boundaries_changed |= boundary_choice(ui, "left", left, right);
+        boundaries_changed |= boundary_choice(ui, "right", right, left);
+        boundaries_changed |= boundary_choice(ui, "bottom", bottom, top);
+        boundaries_changed |= boundary_choice(ui, "top", top, bottom);
+        if boundaries_changed {
+            fluid.set_boundaries(*boundaries);
+        }
+
+        let mut obstacle_changed = false;
+        obstacle_changed |= ui
+            .checkbox(&mut settings.obstacle, "obstacle (drag with the mouse)")
+            .changed();
+        obstacle_changed |= ui
+            .add(
+                egui::Slider::new(&mut settings.obstacle_radius, 5.0..=200.0)
+                    .text("obstacle radius"),
+            )
+            .changed();
+        if obstacle_changed {
+            place_obstacles(fluid, settings, rect);
+        }
+
+        ui.add(egui::Slider::new(&mut settings.brush.radius, 1.0..=200.0).text("brush radius"))
+            .changed();
+        ui.add(egui::Slider::new(&mut settings.brush.force, 0.0..=10.0).text("brush force"))
+            .changed();
+        ui.add(egui::Slider::new(&mut settings.brush.falloff, 0.0..=10.0).text("brush falloff"))
+            .changed();
+
+        let particle_opt = &mut settings.particle_opt;
+        ui.horizontal(|ui| {
+            ui.label("particles");
+            for style in ParticleStyle::ALL {
+                ui.radio_value(&mut particle_opt.style, style, style.name());
+            }
+            ui.checkbox(&mut particle_opt.paint, "paint");
+        });
+        ui.horizontal(|ui| {
+            for interpolation in Interpolation::ALL {
+                ui.radio_value(
+                    &mut particles.interpolation,
+                    interpolation,
+                    interpolation.name(),
+                );
+            }
+            for integrator in Integrator::ALL {
+                ui.radio_value(&mut particles.integrator, integrator, integrator.name());
+            }
+        });
+        ui.add(egui::Slider::new(&mut particles.lifetime, 0.0..=100.0).text("particle lifetime"))
+            .changed();
+        ui.add(egui::Slider::new(&mut particles.trail_length, 0..=50).text("trail length"))
+            .changed();
+        ui.add(egui::Slider::new(&mut particle_opt.size, 0.5..=5.0).text("particle size"))
+            .changed();
+        ui.add(egui::Slider::new(&mut particle_opt.spacing, 2.0..=50.0).text("grid spacing"))
+            .changed();
+        ui.horizontal(|ui| {
+            if ui.button("seed grid").clicked() {
+                particles.seed_grid(particle_opt.spacing, rect);
+            }
+            if ui.button("seed random").clicked() {
+                particles.seed_random(10_000, rect);
+            }
+            if ui.button("clear").clicked() {
+                particles.clear();
+            }
+            ui.label(format!("{} particles", particles.len()));
+        });
+
+        let divergence = active(fluid, mac).divergence();
+        ui.label(format!(
+            "divergence max {:.2e} rms {:.2e}",
+            divergence.max, divergence.rms
+        ));
+
+        let export = &mut settings.export;
+        ui.horizontal(|ui| {
+            ui.label("export");
+            for format in Format::ALL {
+                ui.radio_value(&mut export.format, format, format.name());
+            }
+            ui.checkbox(&mut settings.export_series, "series");
+        });
+        ui.add(egui::Slider::new(&mut export.every, 1..=100).text("steps between frames"))
+            .changed();
+
+        ui.collapsing("diagnostics", |ui| {
+            if mac.is_some() {
+                ui.label("only for the collocated grid");
+                return;
+            }
+            let diagnostics = fluid.diagnostics();
+            for quantity in Quantity::ALL {
+                let values = diagnostics.series(quantity);
+                let latest = values.last().copied().unwrap_or_default();
+                ui.label(format!("{} {:.3e}", quantity.name(), latest));
+                ui.add(
+                    Plot::new(quantity.name())
+                        .line(Line::new(Values::from_ys_f32(&values)))
+                        .height(40.0)
+                        .show_axes([false, false])
+                        .allow_drag(false)
+                        .allow_zoom(false),
+                );
+            }
+            if ui.button("save csv").clicked() {
+                save_diagnostics(app, fluid);
+            }
+        });
     });
+    let over_ui = ctx.wants_pointer_input();
+    let dt = if settings.adaptive_dt && model.mac.is_none() {
+        settings.adaptive.dt(&model.fluid, settings.dt)
+    } else {
+        settings.dt
+    };
+
+    let mouse = app.mouse.position();
+    if settings.obstacle {
+        let dragged = app.mouse.buttons.left().is_down() && !over_ui;
+        let by = if dragged {
+            mouse - model.mouse
+        } else {
+            Vec2::ZERO
+        };
+        model.fluid.move_obstacle(0, by, dt, rect);
+    }
+
+    model.fluid.set_advection(settings.advection);
+    model.fluid.set_vorticity(settings.vorticity);
+    model
+        .fluid
+        .set_dissipation(settings.velocity_dissipation, settings.density_dissipation);
+    model.fluid.set_buoyancy(settings.buoyancy);
+    if settings.heat_source && model.mac.is_none() {
+        let source = rect.mid_bottom() + vec2(0.0, rect.h() / 8.0);
+        model.fluid.add_heat(
+            source,
+            &settings.heat_brush,
+            settings.buoyancy.ambient + settings.heat_temperature,
+            rect,
+        );
+        let amount = settings.dens_opt.input_amount;
+        model
+            .fluid
+            .splat(source, &settings.heat_brush, &[amount; 3], Vec2::ZERO, rect);
+    }
+
+    let sim = active(&mut model.fluid, &mut model.mac);
+    let color = settings.dens_opt.splat_color().rgb(app.time);
+    let painting = (app.mouse.buttons.right().is_down()
+        || (app.mouse.buttons.left().is_down() && !settings.obstacle))
+        && !over_ui;
+    if painting {
+        let amount = settings.dens_opt.input_amount;
+        let amounts = if settings.dens_opt.rgb {
+            color.map(|c| c * amount).to_vec()
+        } else {
+            vec![amount]
+        };
+        sim.stroke(model.mouse, mouse, dt, &settings.brush, &amounts, rect);
+        if settings.particle_opt.paint {
+            model.particles.paint(
+                mouse,
+                settings.brush.radius,
+                settings.particle_opt.per_frame,
+                rect,
+            );
+        }
+    }
+    model.mouse = mouse;
 
     let pos = rect.xy();
 
     for i in -1..1 {
         for j in -1..1 {
-            model.fluid.add_density(
-                vec2(pos.x + i as f32, pos.y + j as f32),
-                settings.dens_opt.input_amount * random::<f32>(),
-                rect,
-            );
+            let amount = settings.dens_opt.input_amount * random::<f32>();
+            let pos = vec2(pos.x + i as f32, pos.y + j as f32);
+            if settings.dens_opt.rgb {
+                sim.add_dye(pos, &color.map(|c| c * amount), rect);
+            } else {
+                sim.add_density(pos, amount, rect);
+            }
         }
     }
 
     let angle = random::<f32>();
-    model.fluid.add_velocity(
+    sim.add_velocity(
         vec2(pos.x as f32, pos.y as f32),
         settings.vel_opt.input_amount * vec2(angle.cos(), angle.sin()),
         rect,
     );
 
-    model.fluid.step(
-        model.settings.vel_opt.diff,
-        model.settings.dens_opt.visc,
-        model.settings.dt,
-        model.settings.iter,
+    sim.set_parallel(settings.parallel);
+    let tolerance = if settings.early_stop {
+        settings.tolerance
+    } else {
+        0.0
+    };
+    sim.set_pressure_solver(settings.pressure, tolerance);
+    sim.step(
+        settings.vel_opt.diff,
+        settings.dens_opt.visc,
+        dt,
+        settings.iter,
     );
+    model.particles.step(sim, dt);
+    model.steps += 1;
+    if settings.export_series {
+        let written = settings.export.step(sim, model.steps);
+        report_export(&settings.export, written);
+    }
 }
 
 struct DensOpt {
     draw_dens: bool,
     dens_color: DensColor,
+    /// Red, green and blue dye channels instead of one density
+    rgb: bool,
+    /// Cycle the dye's hue over time, otherwise it's the dens hue
+    colorful: bool,
     visc: f32,
     input_amount: f32,
 }
 
+impl DensOpt {
+    fn channels(&self) -> usize {
+        if self.rgb {
+            3
+        } else {
+            1
+        }
+    }
+
+    fn display(&self) -> DyeDisplay {
+        if self.rgb {
+            DyeDisplay::Rgb
+        } else {
+            DyeDisplay::Density(self.dens_color)
+        }
+    }
+
+    fn splat_color(&self) -> SplatColor {
+        if self.colorful {
+            SplatColor::Colorful { speed: 0.1 }
+        } else {
+            SplatColor::Fixed {
+                hue: self.dens_color.hue,
+                sat: self.dens_color.sat,
+                value: 1.0,
+            }
+        }
+    }
+}
+
+struct ParticleOpt {
+    draw_particles: bool,
+    style: ParticleStyle,
+    /// Width of the dots or trails
+    size: f32,
+    color: Hsv,
+    /// Seed particles under the brush while painting
+    paint: bool,
+    per_frame: usize,
+    /// Distance between the particles seeded on a grid
+    spacing: f32,
+}
+
 struct VelOpt {
     diff: f32,
     draw_vel: bool,
     line_length: f32,
     color: Hsv,
     input_amount: f32,
+    /// Speed drawn at full brightness in the velocity background
+    max_speed: f32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Background {
+    Dye,
+    /// The velocity coloured by direction and speed
+    Velocity,
+    /// A scalar through its colour map
+    Scalar,
+    /// Noise smeared along the flow by line integral convolution
+    Lic,
+}
+
+impl Background {
+    const ALL: [Background; 4] = [
+        Background::Dye,
+        Background::Velocity,
+        Background::Scalar,
+        Background::Lic,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Background::Dye => "dye",
+            Background::Velocity => "velocity",
+            Background::Scalar => "scalar",
+            Background::Lic => "lic",
+        }
+    }
+}
+
+struct MapOpt {
+    map: ColorMap,
+    /// Use the custom colours instead of `map`
+    custom: bool,
+    /// Map a fixed range instead of the frame's own, which flickers less
+    fixed: bool,
+    /// Top of the fixed range, which goes down to zero or to minus this
+    /// for signed scalars
+    max: f32,
+}
+
+impl MapOpt {
+    fn new(map: ColorMap, max: f32) -> Self {
+        Self {
+            map,
+            custom: false,
+            fixed: false,
+            max,
+        }
+    }
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
@@ -200,14 +1029,24 @@ fn view(app: &App, model: &Model, frame: Frame) {
 
     draw.background().color(BLACK);
 
-    if model.settings.dens_opt.draw_dens {
-        model
-            .fluid
-            .draw_dens(&draw, app.window_rect(), model.settings.dens_opt.dens_color);
+    let sim: &dyn FluidSim = match &model.mac {
+        Some(mac) => mac,
+        None => &model.fluid,
+    };
+
+    if model.settings.dens_opt.draw_dens || model.settings.background != Background::Dye {
+        let [width, height] = model.texture.size();
+        let image = field_image(model, (width, height));
+        model.texture.upload_data(
+            app.main_window().device(),
+            &mut *frame.command_encoder(),
+            image.as_flat_samples().as_slice(),
+        );
+        draw.texture(&model.texture).wh(app.window_rect().wh());
     }
 
     if model.settings.vel_opt.draw_vel {
-        model.fluid.draw_vel(
+        sim.draw_vel(
             &draw,
             app.window_rect(),
             model.settings.vel_opt.line_length,
@@ -215,6 +1054,26 @@ fn view(app: &App, model: &Model, frame: Frame) {
         );
     }
 
+    if model.settings.draw_streamlines {
+        for line in sim.streamlines(&model.settings.streamlines, app.window_rect()) {
+            draw.polyline()
+                .weight(1.5)
+                .points(line)
+                .color(model.settings.vel_opt.color);
+        }
+    }
+
+    let particle_opt = &model.settings.particle_opt;
+    if particle_opt.draw_particles {
+        model.particles.draw(
+            &draw,
+            app.window_rect(),
+            particle_opt.style,
+            particle_opt.size,
+            particle_opt.color,
+        );
+    }
+
     draw.to_frame(app, &frame).unwrap();
 
     let _draw_to_frame = model.egui.draw_to_frame(&frame);
@@ -0,0 +1,251 @@
+//! Solid obstacles the fluid flows around
+//!
+//! Obstacles are shapes in window coordinates, rasterised onto the grid
+//! with the same mapping used to draw it, or a black and white image
+//! stretched over the grid. The solver leaves solid cells out of its
+//! sweeps and fills them in afterwards, like the boundary cells, so
+//! nothing flows through them and moving obstacles drag the fluid along.
+
+use std::path::Path;
+
+use nannou::{
+    image::{self, DynamicImage},
+    prelude::{vec2, Rect, Vec2},
+};
+use ndarray::Array2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Circle {
+        centre: Vec2,
+        radius: f32,
+    },
+    Rect(Rect),
+    /// Vertices in order, filled with the even-odd rule
+    Polygon(Vec<Vec2>),
+}
+
+impl Shape {
+    pub fn contains(&self, point: Vec2) -> bool {
+        match self {
+            Shape::Circle { centre, radius } => centre.distance_squared(point) <= radius * radius,
+            Shape::Rect(rect) => rect.contains(point),
+            Shape::Polygon(vertices) => {
+                let mut inside = false;
+                let mut prev = match vertices.last() {
+                    Some(&last) => last,
+                    None => return false,
+                };
+                for &vertex in vertices {
+                    // does the edge cross the horizontal ray to the right of point
+                    if (vertex.y > point.y) != (prev.y > point.y)
+                        && point.x
+                            < (prev.x - vertex.x) * (point.y - vertex.y) / (prev.y - vertex.y)
+                                + vertex.x
+                    {
+                        inside = !inside;
+                    }
+                    prev = vertex;
+                }
+                inside
+            }
+        }
+    }
+
+    pub fn translate(&mut self, by: Vec2) {
+        match self {
+            Shape::Circle { centre, .. } => *centre += by,
+            Shape::Rect(rect) => {
+                *rect = Rect::from_x_y_w_h(rect.x() + by.x, rect.y() + by.y, rect.w(), rect.h())
+            }
+            Shape::Polygon(vertices) => vertices.iter_mut().for_each(|v| *v += by),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Obstacle {
+    pub shape: Shape,
+    /// Velocity the obstacle moves the fluid with, in the same units as
+    /// the fluid's velocity
+    pub velocity: Vec2,
+}
+
+impl Obstacle {
+    /// Obstacle standing still
+    pub fn new(shape: Shape) -> Self {
+        Self {
+            shape,
+            velocity: Vec2::ZERO,
+        }
+    }
+}
+
+/// Solid cells from an image stretched over a grid of `size`, dark pixels
+/// are solid. The image's top row is the top of the grid, and an empty
+/// image leaves every cell open.
+pub fn image_mask(image: &DynamicImage, size: (usize, usize)) -> Array2<bool> {
+    let luma = image.to_luma8();
+    let (w, h) = luma.dimensions();
+    if w == 0 || h == 0 {
+        return Array2::from_elem(size, false);
+    }
+    Array2::from_shape_fn(size, |(x, y)| {
+        let px = ((x as f32 + 0.5) / size.0 as f32 * w as f32) as u32;
+        let py = ((1.0 - (y as f32 + 0.5) / size.1 as f32) * h as f32) as u32;
+        luma.get_pixel(px.min(w - 1), py.min(h - 1)).0[0] < 128
+    })
+}
+
+/// Load an image and turn it into solid cells, see [`image_mask`]
+pub fn load_image_mask<P: AsRef<Path>>(
+    path: P,
+    size: (usize, usize),
+) -> Result<Array2<bool>, image::ImageError> {
+    Ok(image_mask(&image::open(path)?, size))
+}
+
+/// Which cells are solid and how fast each is moving
+pub(crate) struct Mask {
+    solid: Array2<bool>,
+    /// Cells with at least one solid neighbour
+    near: Array2<bool>,
+    velocity: Array2<Vec2>,
+    /// The solid cells, so filling them in doesn't scan the whole grid
+    cells: Vec<(usize, usize)>,
+}
+
+impl Mask {
+    pub(crate) fn new(size: (usize, usize)) -> Self {
+        Self {
+            solid: Array2::from_elem(size, false),
+            near: Array2::from_elem(size, false),
+            velocity: Array2::from_elem(size, Vec2::ZERO),
+            cells: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_solid(&self, x: usize, y: usize) -> bool {
+        self.solid[(x, y)]
+    }
+
+    pub(crate) fn is_near(&self, x: usize, y: usize) -> bool {
+        self.near[(x, y)]
+    }
+
+    pub(crate) fn velocity(&self, x: usize, y: usize) -> Vec2 {
+        self.velocity[(x, y)]
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for &(x, y) in &self.cells {
+            self.solid[(x, y)] = false;
+            self.velocity[(x, y)] = Vec2::ZERO;
+            for n in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                self.near[n] = false;
+            }
+        }
+        self.cells.clear();
+    }
+
+    /// Make the interior cells where `inside` holds solid, moving with
+    /// `velocity`. The boundary ring is left to the edge modes.
+    pub(crate) fn fill(&mut self, velocity: Vec2, inside: impl Fn(usize, usize) -> bool) {
+        let (nx, ny) = self.solid.dim();
+        for x in 1..(nx - 1) {
+            for y in 1..(ny - 1) {
+                if inside(x, y) {
+                    if !self.solid[(x, y)] {
+                        self.cells.push((x, y));
+                        for n in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                            self.near[n] = true;
+                        }
+                    }
+                    self.solid[(x, y)] = true;
+                    self.velocity[(x, y)] = velocity;
+                }
+            }
+        }
+    }
+
+    /// Solid cells take the mean of their fluid neighbours, for anything
+    /// that samples them like advection and drawing. Cells buried inside an
+    /// obstacle are zero.
+    pub(crate) fn set_scalar(&self, array: &mut Array2<f32>) {
+        for &(x, y) in &self.cells {
+            let mut sum = 0.0;
+            let mut count = 0;
+            for n in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if !self.solid[n] {
+                    sum += array[n];
+                    count += 1;
+                }
+            }
+            array[(x, y)] = if count > 0 { sum / count as f32 } else { 0.0 };
+        }
+    }
+
+    /// Solid cells reflect the velocity of their fluid neighbours about
+    /// the obstacle's velocity, like the walls do, for anything that
+    /// samples them. Each component is reflected from the neighbours along
+    /// its own axis.
+    pub(crate) fn set_velocity(&self, array: &mut Array2<Vec2>) {
+        for &(x, y) in &self.cells {
+            let moving = self.velocity[(x, y)];
+            let reflect = |neighbours: [(usize, usize); 2], component: fn(Vec2) -> f32| {
+                let fluid: Vec2 = neighbours
+                    .iter()
+                    .filter(|&&n| !self.solid[n])
+                    .fold(vec2(0.0, 0.0), |acc, &n| {
+                        acc + vec2(component(array[n]), 1.0)
+                    });
+                if fluid.y > 0.0 {
+                    2.0 * component(moving) - fluid.x / fluid.y
+                } else {
+                    component(moving)
+                }
+            };
+            array[(x, y)] = vec2(
+                reflect([(x - 1, y), (x + 1, y)], |v| v.x),
+                reflect([(x, y - 1), (x, y + 1)], |v| v.y),
+            );
+        }
+    }
+}
+
+#[test]
+fn test_shapes_contain() {
+    let circle = Shape::Circle {
+        centre: vec2(1.0, 1.0),
+        radius: 2.0,
+    };
+    assert!(circle.contains(vec2(2.0, 2.0)));
+    assert!(!circle.contains(vec2(3.0, 3.0)));
+
+    let mut triangle = Shape::Polygon(vec![vec2(0.0, 0.0), vec2(4.0, 0.0), vec2(0.0, 4.0)]);
+    assert!(triangle.contains(vec2(1.0, 1.0)));
+    assert!(!triangle.contains(vec2(3.0, 3.0)));
+    triangle.translate(vec2(2.0, 2.0));
+    assert!(triangle.contains(vec2(3.0, 3.0)));
+
+    let rect = Shape::Rect(Rect::from_w_h(2.0, 2.0));
+    assert!(rect.contains(vec2(0.5, -0.5)));
+    assert!(!rect.contains(vec2(1.5, 0.0)));
+}
+
+#[test]
+fn test_image_mask() {
+    // black left half
+    let image = image::GrayImage::from_fn(8, 4, |x, _| image::Luma([if x < 4 { 0 } else { 255 }]));
+    let mask = image_mask(&DynamicImage::ImageLuma8(image), (10, 6));
+    assert!(mask[(2, 3)]);
+    assert!(!mask[(7, 3)]);
+}
+
+#[test]
+fn test_empty_image_mask() {
+    for (w, h) in [(0, 0), (0, 4), (8, 0)] {
+        let image = DynamicImage::ImageLuma8(image::GrayImage::new(w, h));
+        assert!(image_mask(&image, (10, 6)).iter().all(|&solid| !solid));
+    }
+}
@@ -0,0 +1,478 @@
+//! Massless tracers carried along by the fluid
+//!
+//! Positions are fractions of the window, so the same particles can be
+//! drawn over any rectangle and survive the grid changing size. Each
+//! particle remembers where it was seeded and goes back there when it
+//! leaves the interior, ends up in an obstacle or reaches its lifetime.
+
+use std::collections::VecDeque;
+
+use nannou::{
+    color::Hsv,
+    geom::Tri,
+    prelude::{random, vec2, Rect, Vec2},
+    Draw,
+};
+use ndarray::Array2;
+use rayon::prelude::*;
+
+use crate::{grid_scale, stencil, FluidSim};
+
+/// How the velocity is read between cell centres
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    #[default]
+    Bilinear,
+    /// Catmull-Rom through the 4x4 nearest cells, smooth across cell
+    /// edges but may overshoot a little
+    Bicubic,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 2] = [Interpolation::Bilinear, Interpolation::Bicubic];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Bilinear => "bilinear",
+            Interpolation::Bicubic => "bicubic",
+        }
+    }
+}
+
+/// How a particle is moved along the velocity over a step
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// Midpoint method, samples the velocity twice
+    #[default]
+    Rk2,
+    /// Classic fourth order Runge-Kutta, samples it four times
+    Rk4,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 2] = [Integrator::Rk2, Integrator::Rk4];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::Rk2 => "RK2",
+            Integrator::Rk4 => "RK4",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ParticleStyle {
+    #[default]
+    Dots,
+    /// A line through the last few positions
+    Trails,
+}
+
+impl ParticleStyle {
+    pub const ALL: [ParticleStyle; 2] = [ParticleStyle::Dots, ParticleStyle::Trails];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParticleStyle::Dots => "dots",
+            ParticleStyle::Trails => "trails",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Particle {
+    pos: Vec2,
+    /// Where it was seeded and goes back to
+    origin: Vec2,
+    age: f32,
+    /// Earlier positions, oldest first
+    trail: VecDeque<Vec2>,
+}
+
+impl Particle {
+    fn new(pos: Vec2, age: f32) -> Self {
+        Self {
+            pos,
+            origin: pos,
+            age,
+            trail: VecDeque::new(),
+        }
+    }
+
+    fn respawn(&mut self) {
+        self.pos = self.origin;
+        self.age = 0.0;
+        self.trail.clear();
+    }
+}
+
+pub struct Particles {
+    particles: Vec<Particle>,
+    /// Where the next seed goes once there are `capacity` particles,
+    /// replacing the oldest seed
+    next: usize,
+    /// Most particles kept at once
+    pub capacity: usize,
+    pub interpolation: Interpolation,
+    pub integrator: Integrator,
+    /// Time before a particle goes back to where it was seeded, zero for
+    /// never
+    pub lifetime: f32,
+    /// Positions remembered for drawing trails
+    pub trail_length: usize,
+}
+
+impl Particles {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            particles: Vec::new(),
+            next: 0,
+            capacity,
+            interpolation: Interpolation::default(),
+            integrator: Integrator::default(),
+            lifetime: 0.0,
+            trail_length: 10,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.next = 0;
+    }
+
+    /// Positions in window coordinates
+    pub fn positions(&self, rect: Rect) -> impl Iterator<Item = Vec2> + '_ {
+        self.particles.iter().map(move |p| window_pos(p.pos, rect))
+    }
+
+    /// Add a particle at `pos` in window coordinates. Its age starts at a
+    /// random part of the lifetime so particles seeded together don't all
+    /// respawn together.
+    pub fn seed(&mut self, pos: Vec2, rect: Rect) {
+        let particle = Particle::new(
+            (pos - rect.bottom_left()) / rect.wh(),
+            random::<f32>() * self.lifetime,
+        );
+        if self.particles.len() < self.capacity {
+            self.particles.push(particle);
+        } else if self.capacity > 0 {
+            self.particles[self.next] = particle;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    /// Seed `count` particles at random in the disc of `radius` around
+    /// `centre`, like a brush
+    pub fn paint(&mut self, centre: Vec2, radius: f32, count: usize, rect: Rect) {
+        for _ in 0..count {
+            let angle = random::<f32>() * std::f32::consts::TAU;
+            // the square root spreads them evenly over the area
+            let r = radius * random::<f32>().sqrt();
+            self.seed(centre + r * vec2(angle.cos(), angle.sin()), rect);
+        }
+    }
+
+    /// Seed a particle every `spacing` in window coordinates, starting
+    /// half a spacing in from the bottom left
+    pub fn seed_grid(&mut self, spacing: f32, rect: Rect) {
+        if spacing <= 0.0 {
+            return;
+        }
+        let counts = (rect.wh() / spacing).floor();
+        for i in 0..counts.x as usize {
+            for j in 0..counts.y as usize {
+                let offset = (vec2(i as f32, j as f32) + 0.5) * spacing;
+                self.seed(rect.bottom_left() + offset, rect);
+            }
+        }
+    }
+
+    /// Seed `count` particles anywhere in the window
+    pub fn seed_random(&mut self, count: usize, rect: Rect) {
+        for _ in 0..count {
+            let fraction = vec2(random::<f32>(), random::<f32>());
+            self.seed(rect.bottom_left() + fraction * rect.wh(), rect);
+        }
+    }
+
+    /// Move every particle along the fluid's velocity over a step of `dt`
+    pub fn step(&mut self, fluid: &dyn FluidSim, dt: f32) {
+        let velocity = fluid.velocity();
+        let (nx, ny) = velocity.dim();
+        let cells = vec2(nx as f32, ny as f32);
+        // fractions of the window per unit of velocity per unit time
+        let speed = grid_scale(&[nx, ny]) / cells;
+        let interpolation = self.interpolation;
+        let sample = |pos: Vec2| speed * sample(velocity, pos * cells, interpolation);
+        let (integrator, lifetime, trail_length) =
+            (self.integrator, self.lifetime, self.trail_length);
+        let solid = fluid.solid();
+        let inside = |pos: Vec2| {
+            let cell = pos * cells;
+            cell.x >= 1.0
+                && cell.y >= 1.0
+                && cell.x < cells.x - 1.0
+                && cell.y < cells.y - 1.0
+                && !solid[(cell.x as usize, cell.y as usize)]
+        };
+        self.particles.par_iter_mut().for_each(|particle| {
+            if trail_length > 0 {
+                if particle.trail.len() >= trail_length {
+                    particle.trail.pop_front();
+                }
+                particle.trail.push_back(particle.pos);
+            }
+            particle.pos = integrate(particle.pos, dt, integrator, sample);
+            particle.age += dt;
+            let expired = lifetime > 0.0 && particle.age >= lifetime;
+            if expired || !inside(particle.pos) {
+                particle.respawn();
+            }
+        });
+    }
+
+    /// Draw the particles over `wrect`, dots `size` across or trails
+    /// `size` thick. They all go into one mesh, so tens of thousands of
+    /// them are a single shape rather than one each.
+    pub fn draw(&self, draw: &Draw, wrect: Rect, style: ParticleStyle, size: f32, color: Hsv) {
+        let tris = self.quads(wrect, style, size).flat_map(|[a, b, c, d]| {
+            let [a, b, c, d] = [a, b, c, d].map(|corner| corner.extend(0.0));
+            [Tri([a, b, c]), Tri([a, c, d])]
+        });
+        draw.mesh().tris(tris).color(color);
+    }
+
+    /// Corners of the squares and trail segments that make up the
+    /// particles, going round each one
+    fn quads(
+        &self,
+        wrect: Rect,
+        style: ParticleStyle,
+        size: f32,
+    ) -> impl Iterator<Item = [Vec2; 4]> + '_ {
+        let half = 0.5 * size;
+        self.particles.iter().flat_map(move |particle| {
+            let pos = window_pos(particle.pos, wrect);
+            let quads: Vec<[Vec2; 4]> = match style {
+                ParticleStyle::Dots => {
+                    let (x, y) = (vec2(half, 0.0), vec2(0.0, half));
+                    vec![[pos - x - y, pos + x - y, pos + x + y, pos - x + y]]
+                }
+                ParticleStyle::Trails => {
+                    let points: Vec<Vec2> = particle
+                        .trail
+                        .iter()
+                        .map(|p| window_pos(*p, wrect))
+                        .chain(std::iter::once(pos))
+                        .collect();
+                    points
+                        .windows(2)
+                        .map(|ends| {
+                            let across = half * (ends[1] - ends[0]).perp().normalize_or_zero();
+                            [
+                                ends[0] - across,
+                                ends[1] - across,
+                                ends[1] + across,
+                                ends[0] + across,
+                            ]
+                        })
+                        .collect()
+                }
+            };
+            quads
+        })
+    }
+}
+
+fn window_pos(fraction: Vec2, rect: Rect) -> Vec2 {
+    rect.bottom_left() + fraction * rect.wh()
+}
+
+/// New position after moving along `velocity` for `dt`
+fn integrate(pos: Vec2, dt: f32, integrator: Integrator, velocity: impl Fn(Vec2) -> Vec2) -> Vec2 {
+    match integrator {
+        Integrator::Rk2 => {
+            let mid = pos + 0.5 * dt * velocity(pos);
+            pos + dt * velocity(mid)
+        }
+        Integrator::Rk4 => {
+            let k1 = velocity(pos);
+            let k2 = velocity(pos + 0.5 * dt * k1);
+            let k3 = velocity(pos + 0.5 * dt * k2);
+            let k4 = velocity(pos + dt * k3);
+            pos + dt / 6.0 * (k1 + 2.0 * k2 + 2.0 * k3 + k4)
+        }
+    }
+}
+
+/// Velocity at `pos` in cells, where cell `(x, y)` covers `[x, x + 1)`
+/// and its velocity is at its centre. Outside the centres the nearest
+/// edge is used.
+fn sample(velocity: &Array2<Vec2>, pos: Vec2, interpolation: Interpolation) -> Vec2 {
+    match interpolation {
+        Interpolation::Bilinear => crate::sample(velocity, pos),
+        Interpolation::Bicubic => {
+            let (nx, ny) = velocity.dim();
+            let ((x, y), frac) = stencil((nx, ny), pos);
+            let at = |i: isize, j: isize| {
+                let i = (x as isize + i).clamp(0, nx as isize - 1) as usize;
+                let j = (y as isize + j).clamp(0, ny as isize - 1) as usize;
+                velocity[(i, j)]
+            };
+            let column = |i| catmull_rom([at(i, -1), at(i, 0), at(i, 1), at(i, 2)], frac.y);
+            catmull_rom([column(-1), column(0), column(1), column(2)], frac.x)
+        }
+    }
+}
+
+/// Cubic through `p[1]` at `t = 0` and `p[2]` at `t = 1`, with slopes
+/// from their neighbours
+fn catmull_rom(p: [Vec2; 4], t: f32) -> Vec2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p[1]
+        + (p[2] - p[0]) * t
+        + (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t2
+        + (3.0 * p[1] - p[0] - 3.0 * p[2] + p[3]) * t3)
+}
+
+#[test]
+fn test_interpolation_is_exact_for_linear_fields() {
+    let velocity =
+        Array2::from_shape_fn((10, 10), |(x, y)| vec2(x as f32, 2.0 * y as f32 - x as f32));
+    for interpolation in Interpolation::ALL {
+        for pos in [vec2(3.7, 2.2), vec2(2.5, 7.4), vec2(5.0, 4.0)] {
+            let index = pos - 0.5;
+            // away from the edges, where the bicubic stencil is clamped
+            let expected = vec2(index.x, 2.0 * index.y - index.x);
+            let got = sample(&velocity, pos, interpolation);
+            assert!(
+                (got - expected).length() < 1e-4,
+                "{:?} {:?} {:?}",
+                interpolation,
+                got,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn test_rk4_stays_on_the_circle() {
+    let rotation = |p: Vec2| vec2(-p.y, p.x);
+    let drift = |integrator| {
+        let mut pos = vec2(1.0, 0.0);
+        for _ in 0..100 {
+            pos = integrate(pos, 0.1, integrator, rotation);
+        }
+        (pos.length() - 1.0).abs()
+    };
+    let (rk2, rk4) = (drift(Integrator::Rk2), drift(Integrator::Rk4));
+    assert!(rk2 < 1e-2, "{}", rk2);
+    assert!(rk4 < 1e-2 * rk2, "{} {}", rk4, rk2);
+}
+
+#[test]
+fn test_particles_follow_flow_and_respawn() {
+    use crate::{
+        boundary::{Boundaries, Boundary},
+        fluid_object::FluidCube,
+    };
+
+    let rect = Rect::from_w_h(100.0, 100.0);
+    // a uniform flow to the right through periodic sides, which a step
+    // without time only copies onto the ghost cells
+    let boundaries = Boundaries {
+        left: Boundary::Periodic,
+        right: Boundary::Periodic,
+        ..Boundaries::default()
+    };
+    let mut fluid = FluidCube::with_boundaries((34, 34), boundaries);
+    for x in 1..33 {
+        for y in 1..33 {
+            fluid.add_velocity(
+                vec2(x as f32, y as f32) / 34.0 * 100.0 - 49.0,
+                vec2(1.0, 0.0),
+                rect,
+            );
+        }
+    }
+    fluid.step(0.0, 0.0, 0.0, 20);
+    let mut particles = Particles::new(100);
+    particles.seed_grid(25.0, rect);
+    assert_eq!(particles.len(), 16);
+    let start: Vec<Vec2> = particles.positions(rect).collect();
+
+    // a grid scale of 32 cells and 34 cells to the window, so a cell is
+    // 100 / 34 window units
+    particles.step(&fluid, 0.1);
+    for (pos, start) in particles.positions(rect).zip(&start) {
+        let moved = pos - *start;
+        assert!((moved.x - 3.2 * 100.0 / 34.0).abs() < 1e-3, "{:?}", moved);
+        assert!(moved.y.abs() < 1e-4, "{:?}", moved);
+    }
+
+    // the right hand column reaches the ghost cells and starts again
+    particles.step(&fluid, 0.1);
+    let respawned = particles
+        .positions(rect)
+        .zip(&start)
+        .filter(|(pos, start)| *pos == **start)
+        .count();
+    assert_eq!(respawned, 4);
+
+    particles.lifetime = 0.25;
+    particles.step(&fluid, 0.3);
+    assert!(particles
+        .positions(rect)
+        .zip(&start)
+        .all(|(pos, start)| pos == *start));
+}
+
+#[test]
+fn test_seeds_replace_the_oldest() {
+    let rect = Rect::from_w_h(100.0, 100.0);
+    let mut particles = Particles::new(3);
+    for x in 0..5 {
+        particles.seed(vec2(x as f32, 0.0), rect);
+    }
+    let xs: Vec<f32> = particles.positions(rect).map(|p| p.x.round()).collect();
+    assert_eq!(xs, [3.0, 4.0, 2.0]);
+}
+
+#[test]
+fn test_one_quad_per_dot_and_trail_segment() {
+    let rect = Rect::from_w_h(100.0, 100.0);
+    let mut particles = Particles::new(2);
+    particles.seed(vec2(-10.0, 0.0), rect);
+    particles.seed(vec2(10.0, 0.0), rect);
+    let dots: Vec<_> = particles.quads(rect, ParticleStyle::Dots, 2.0).collect();
+    assert_eq!(dots.len(), 2);
+    assert_eq!(dots[0][0], vec2(-11.0, -1.0));
+    assert_eq!(dots[0][2], vec2(-9.0, 1.0));
+
+    // a new particle has no trail yet
+    assert_eq!(particles.quads(rect, ParticleStyle::Trails, 2.0).count(), 0);
+    particles.particles[0]
+        .trail
+        .extend([vec2(0.25, 0.5), vec2(0.375, 0.5)]);
+    let trails: Vec<_> = particles.quads(rect, ParticleStyle::Trails, 2.0).collect();
+    assert_eq!(trails.len(), 2);
+    assert_eq!(
+        trails[0],
+        [
+            vec2(-25.0, -1.0),
+            vec2(-12.5, -1.0),
+            vec2(-12.5, 1.0),
+            vec2(-25.0, 1.0)
+        ]
+    );
+}
@@ -0,0 +1,536 @@
+//! Solving for the pressure that makes the velocity mass conserving
+//!
+//! The pressure satisfies a Poisson equation, scaled by h^2 so each fluid
+//! cell reads `diagonal * p - (sum of the neighbours) = b`, with solid
+//! neighbours mirrored onto the diagonal. Every method starts from zero
+//! and iterates until the residual has shrunk to `tolerance` times the
+//! starting one, or it runs out of iterations.
+
+use std::time::{Duration, Instant};
+
+use nannou::prelude::Vec2;
+use ndarray::Array2;
+
+use crate::{
+    boundary::{set_pressure_boundaries, Boundaries, Boundary},
+    for_rows, lin_solve, neighbour_sum,
+    obstacle::Mask,
+    relax_colour, Domain,
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PressureSolver {
+    /// Every cell at once from its neighbours' old values
+    Jacobi,
+    /// Red-black Gauss-Seidel, each colour from the other's new values
+    #[default]
+    GaussSeidel,
+    /// V-cycles over successively coarser grids, which remove the smooth
+    /// error relaxation is slow on
+    Multigrid,
+    /// Conjugate gradients with a V-cycle as the preconditioner
+    ConjugateGradient,
+}
+
+impl PressureSolver {
+    pub const ALL: [PressureSolver; 4] = [
+        PressureSolver::Jacobi,
+        PressureSolver::GaussSeidel,
+        PressureSolver::Multigrid,
+        PressureSolver::ConjugateGradient,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PressureSolver::Jacobi => "Jacobi",
+            PressureSolver::GaussSeidel => "Gauss-Seidel",
+            PressureSolver::Multigrid => "multigrid",
+            PressureSolver::ConjugateGradient => "PCG",
+        }
+    }
+}
+
+/// How a pressure solve went
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PressureStats {
+    /// Sweeps, V-cycles or conjugate gradient steps
+    pub iterations: usize,
+    /// Root mean square residual relative to the starting one
+    pub residual: f32,
+    pub time: Duration,
+}
+
+/// Relaxation methods only check the residual this often, as working it
+/// out costs about as much as a sweep
+const CHECK_EVERY: usize = 4;
+
+/// Red-black sweeps before and after visiting the coarser grid
+const SMOOTHING: usize = 2;
+
+/// One level of the multigrid hierarchy, half the size of the one above
+struct Level {
+    p: Array2<f32>,
+    b: Array2<f32>,
+    r: Array2<f32>,
+    scratch: Array2<f32>,
+    domain: Domain,
+}
+
+/// Buffers for the methods beyond relaxation, sized once with the grid
+pub(crate) struct Workspace {
+    residual: Array2<f32>,
+    preconditioned: Array2<f32>,
+    search: Array2<f32>,
+    product: Array2<f32>,
+    levels: Vec<Level>,
+}
+
+impl Workspace {
+    pub(crate) fn new(size: (usize, usize), boundaries: Boundaries) -> Self {
+        let mut levels = Vec::new();
+        let (mut mx, mut my) = (size.0 - 2, size.1 - 2);
+        while mx.min(my) > 2 {
+            mx = mx.div_ceil(2);
+            my = my.div_ceil(2);
+            let coarse = (mx + 2, my + 2);
+            levels.push(Level {
+                p: Array2::zeros(coarse),
+                b: Array2::zeros(coarse),
+                r: Array2::zeros(coarse),
+                scratch: Array2::zeros(coarse),
+                domain: Domain::new(coarse, boundaries),
+            });
+        }
+        Self {
+            residual: Array2::zeros(size),
+            preconditioned: Array2::zeros(size),
+            search: Array2::zeros(size),
+            product: Array2::zeros(size),
+            levels,
+        }
+    }
+}
+
+/// Fill in the ghost and solid cells of a pressure or a correction to it
+fn bound(p: &mut Array2<f32>, domain: &Domain) {
+    set_pressure_boundaries(p, &domain.boundaries);
+    domain.obstacles.set_scalar(p);
+}
+
+/// Without an open edge the pressure is only defined up to a constant,
+/// and there's only a solution when `b` sums to zero
+fn singular(boundaries: &Boundaries) -> bool {
+    ![
+        boundaries.left,
+        boundaries.right,
+        boundaries.bottom,
+        boundaries.top,
+    ]
+    .contains(&Boundary::Open)
+}
+
+/// Whether the cell is solved for, a fluid cell walled in on all sides
+/// has nothing to solve
+fn solved(x: usize, y: usize, obstacles: &Mask) -> bool {
+    !obstacles.is_solid(x, y)
+        && [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+            .iter()
+            .any(|&(i, j)| !obstacles.is_solid(i, j))
+}
+
+/// The diagonal and the sum of the neighbours of a fluid cell's row, or
+/// None for cells that aren't solved for
+fn row(p: &Array2<f32>, cell: (usize, usize), obstacles: &Mask) -> Option<(f32, f32)> {
+    solved(cell.0, cell.1, obstacles).then(|| {
+        let (sum, weight) = neighbour_sum(p, cell, obstacles);
+        (4.0 - weight, sum)
+    })
+}
+
+/// `out = b - A p` at the solved cells and zero elsewhere, `p` must have
+/// its boundaries filled in
+fn residual(out: &mut Array2<f32>, p: &Array2<f32>, b: &Array2<f32>, domain: &Domain) {
+    let obstacles = &domain.obstacles;
+    for_rows(out, domain.parallel, |x, cells| {
+        for (y, cell) in cells.iter_mut().enumerate().take(p.dim().1 - 1).skip(1) {
+            *cell = row(p, (x, y), obstacles).map_or(0.0, |(diagonal, sum)| {
+                b[(x, y)] - (diagonal * p[(x, y)] - sum)
+            });
+        }
+    });
+}
+
+/// Sum of `a * b` over the solved cells, in a fixed order so threads
+/// don't change the result
+fn dot(a: &Array2<f32>, b: &Array2<f32>, domain: &Domain) -> f64 {
+    let (nx, ny) = a.dim();
+    let mut sum = 0.0;
+    for x in 1..(nx - 1) {
+        for y in 1..(ny - 1) {
+            if solved(x, y, &domain.obstacles) {
+                sum += a[(x, y)] as f64 * b[(x, y)] as f64;
+            }
+        }
+    }
+    sum
+}
+
+/// Subtract the mean over the solved cells
+fn remove_mean(array: &mut Array2<f32>, domain: &Domain) {
+    let (nx, ny) = array.dim();
+    let (mut sum, mut count) = (0.0, 0);
+    for x in 1..(nx - 1) {
+        for y in 1..(ny - 1) {
+            if solved(x, y, &domain.obstacles) {
+                sum += array[(x, y)] as f64;
+                count += 1;
+            }
+        }
+    }
+    if count > 0 {
+        let mean = (sum / count as f64) as f32;
+        let obstacles = &domain.obstacles;
+        for_rows(array, domain.parallel, |x, cells| {
+            for (y, cell) in cells.iter_mut().enumerate().take(ny - 1).skip(1) {
+                if solved(x, y, obstacles) {
+                    *cell -= mean;
+                }
+            }
+        });
+    }
+}
+
+/// Make `b` solvable when the pressure is only defined up to a constant
+pub(crate) fn make_solvable(b: &mut Array2<f32>, domain: &Domain) {
+    if singular(&domain.boundaries) {
+        remove_mean(b, domain);
+    }
+}
+
+/// Fraction of the way each Jacobi sweep moves towards the new values.
+/// Undamped, the checkerboard error flips sign every sweep and never
+/// shrinks.
+const JACOBI_WEIGHT: f32 = 0.8;
+
+/// Update every cell at once from the old values, through `scratch`
+fn jacobi_sweep(p: &mut Array2<f32>, b: &Array2<f32>, scratch: &mut Array2<f32>, domain: &Domain) {
+    let current = &*p;
+    let obstacles = &domain.obstacles;
+    for_rows(scratch, domain.parallel, |x, cells| {
+        for (y, cell) in cells.iter_mut().enumerate().take(b.dim().1 - 1).skip(1) {
+            let old = current[(x, y)];
+            *cell = row(current, (x, y), obstacles).map_or(old, |(diagonal, sum)| {
+                old + JACOBI_WEIGHT * ((b[(x, y)] + sum) / diagonal - old)
+            });
+        }
+    });
+    std::mem::swap(p, scratch);
+    bound(p, domain);
+}
+
+/// Red-black sweeps, black first when `backwards` so a sweep forwards and
+/// one backwards make a symmetric smoother
+fn smooth(
+    p: &mut Array2<f32>,
+    b: &Array2<f32>,
+    scratch: &mut Array2<f32>,
+    sweeps: usize,
+    backwards: bool,
+    domain: &Domain,
+) {
+    let order = if backwards { [1, 0] } else { [0, 1] };
+    for _ in 0..sweeps {
+        for colour in order {
+            relax_colour(p, b, scratch, 1.0, 4.0, colour, domain);
+        }
+        bound(p, domain);
+    }
+}
+
+/// Fine cells covered by the coarse cell `index` along an axis with
+/// `len` interior cells
+fn children(index: usize, len: usize) -> std::ops::Range<usize> {
+    (2 * index - 1)..(2 * index + 1).min(len + 1)
+}
+
+/// Make each coarse cell solid when all the fine cells it covers are
+fn coarsen(fine: &Mask, fine_dim: (usize, usize), coarse: &mut Mask) {
+    let (mx, my) = (fine_dim.0 - 2, fine_dim.1 - 2);
+    coarse.clear();
+    coarse.fill(Vec2::ZERO, |x, y| {
+        children(x, mx).all(|i| children(y, my).all(|j| fine.is_solid(i, j)))
+    });
+}
+
+/// Each coarse cell gets the sum of the residuals of the fine cells it
+/// covers, the sum rather than the mean as the scaling by h^2 quadruples
+fn restrict(fine: &Array2<f32>, coarse: &mut Array2<f32>, domain: &Domain) {
+    let (mx, my) = (fine.dim().0 - 2, fine.dim().1 - 2);
+    coarse.fill(0.0);
+    for_rows(coarse, domain.parallel, |x, cells| {
+        let len = cells.len();
+        for (y, cell) in cells.iter_mut().enumerate().take(len - 1).skip(1) {
+            *cell = children(x, mx)
+                .flat_map(|i| children(y, my).map(move |j| (i, j)))
+                .map(|cell| fine[cell])
+                .sum();
+        }
+    });
+}
+
+/// Add the coarse correction to each fine cell it covers
+fn prolong(coarse: &Array2<f32>, fine: &mut Array2<f32>, domain: &Domain) {
+    let obstacles = &domain.obstacles;
+    for_rows(fine, domain.parallel, |x, cells| {
+        let len = cells.len();
+        for (y, cell) in cells.iter_mut().enumerate().take(len - 1).skip(1) {
+            if !obstacles.is_solid(x, y) {
+                *cell += coarse[(x.div_ceil(2), y.div_ceil(2))];
+            }
+        }
+    });
+    bound(fine, domain);
+}
+
+/// One V-cycle improving `p`, smoothing on the way down and back up with
+/// the coarse levels solving for the correction. Starting from zero it's
+/// a symmetric approximate inverse, so it can precondition conjugate
+/// gradients.
+fn v_cycle(
+    p: &mut Array2<f32>,
+    b: &Array2<f32>,
+    r: &mut Array2<f32>,
+    scratch: &mut Array2<f32>,
+    domain: &Domain,
+    coarse: &mut [Level],
+) {
+    match coarse.split_first_mut() {
+        None => {
+            // few enough cells to relax until the error has crossed the grid
+            let len = p.dim().0.max(p.dim().1);
+            let sweeps = (len * len).max(8);
+            smooth(p, b, scratch, sweeps, false, domain);
+            smooth(p, b, scratch, sweeps, true, domain);
+        }
+        Some((level, rest)) => {
+            smooth(p, b, scratch, SMOOTHING, false, domain);
+            residual(r, p, b, domain);
+            restrict(r, &mut level.b, &level.domain);
+            level.p.fill(0.0);
+            v_cycle(
+                &mut level.p,
+                &level.b,
+                &mut level.r,
+                &mut level.scratch,
+                &level.domain,
+                rest,
+            );
+            prolong(&level.p, p, domain);
+            smooth(p, b, scratch, SMOOTHING, true, domain);
+        }
+    }
+}
+
+/// Match the coarse levels to the fine grid's boundaries and obstacles
+fn prepare_levels(levels: &mut [Level], domain: &Domain, dim: (usize, usize)) {
+    let mut fine = (&domain.obstacles, dim);
+    for level in levels {
+        level.domain.boundaries = domain.boundaries;
+        level.domain.parallel = domain.parallel;
+        coarsen(fine.0, fine.1, &mut level.domain.obstacles);
+        fine = (&level.domain.obstacles, level.p.dim());
+    }
+}
+
+/// Solve for the pressure `p` given the right hand side `b`, with at most
+/// `iter` iterations of the domain's method
+pub(crate) fn solve(
+    p: &mut Array2<f32>,
+    b: &Array2<f32>,
+    scratch: &mut Array2<f32>,
+    work: &mut Workspace,
+    iter: usize,
+    domain: &Domain,
+) -> PressureStats {
+    let start = Instant::now();
+    p.fill(0.0);
+    bound(p, domain);
+    let initial = dot(b, b, domain).sqrt();
+    if initial == 0.0 {
+        return PressureStats {
+            time: start.elapsed(),
+            ..PressureStats::default()
+        };
+    }
+    let tolerance = domain.tolerance as f64;
+    let relative = |r: &Array2<f32>| dot(r, r, domain).sqrt() / initial;
+
+    let Workspace {
+        residual: r,
+        preconditioned: z,
+        search: d,
+        product: q,
+        levels,
+    } = work;
+    if matches!(
+        domain.pressure,
+        PressureSolver::Multigrid | PressureSolver::ConjugateGradient
+    ) {
+        prepare_levels(levels, domain, p.dim());
+    }
+
+    let mut iterations = 0;
+    let mut error = 1.0;
+    match domain.pressure {
+        PressureSolver::Jacobi | PressureSolver::GaussSeidel => {
+            while iterations < iter && error > tolerance {
+                let sweeps = CHECK_EVERY.min(iter - iterations);
+                if domain.pressure == PressureSolver::Jacobi {
+                    for _ in 0..sweeps {
+                        jacobi_sweep(p, b, scratch, domain);
+                    }
+                } else {
+                    lin_solve(p, b, scratch, 1.0, 4.0, sweeps, domain, |p| {
+                        bound(p, domain)
+                    });
+                }
+                iterations += sweeps;
+                residual(r, p, b, domain);
+                error = relative(r);
+            }
+        }
+        PressureSolver::Multigrid => {
+            while iterations < iter && error > tolerance {
+                v_cycle(p, b, q, scratch, domain, levels);
+                iterations += 1;
+                residual(r, p, b, domain);
+                error = relative(r);
+            }
+        }
+        PressureSolver::ConjugateGradient => {
+            let fix_mean = singular(&domain.boundaries);
+            let precondition = |z: &mut Array2<f32>,
+                                r: &Array2<f32>,
+                                q: &mut Array2<f32>,
+                                scratch: &mut Array2<f32>,
+                                levels: &mut [Level]| {
+                z.fill(0.0);
+                bound(z, domain);
+                v_cycle(z, r, q, scratch, domain, levels);
+                if fix_mean {
+                    remove_mean(z, domain);
+                }
+            };
+            r.assign(b);
+            precondition(z, r, q, scratch, levels);
+            d.assign(z);
+            let mut rz = dot(r, z, domain);
+            while iterations < iter && error > tolerance {
+                bound(d, domain);
+                let (search, obstacles) = (&*d, &domain.obstacles);
+                for_rows(q, domain.parallel, |x, cells| {
+                    let len = cells.len();
+                    for (y, cell) in cells.iter_mut().enumerate().take(len - 1).skip(1) {
+                        *cell = row(search, (x, y), obstacles)
+                            .map_or(0.0, |(diagonal, sum)| diagonal * search[(x, y)] - sum);
+                    }
+                });
+                let alpha = (rz / dot(d, q, domain)) as f32;
+                let (search, product) = (&*d, &*q);
+                for_rows(p, domain.parallel, |x, cells| {
+                    let len = cells.len();
+                    for (y, cell) in cells.iter_mut().enumerate().take(len - 1).skip(1) {
+                        *cell += alpha * search[(x, y)];
+                    }
+                });
+                for_rows(r, domain.parallel, |x, cells| {
+                    let len = cells.len();
+                    for (y, cell) in cells.iter_mut().enumerate().take(len - 1).skip(1) {
+                        *cell -= alpha * product[(x, y)];
+                    }
+                });
+                iterations += 1;
+                error = relative(r);
+                if error <= tolerance {
+                    break;
+                }
+
+                precondition(z, r, q, scratch, levels);
+                let rz_next = dot(r, z, domain);
+                let beta = (rz_next / rz) as f32;
+                rz = rz_next;
+                let preconditioned = &*z;
+                for_rows(d, domain.parallel, |x, cells| {
+                    let len = cells.len();
+                    for (y, cell) in cells.iter_mut().enumerate().take(len - 1).skip(1) {
+                        *cell = preconditioned[(x, y)] + beta * *cell;
+                    }
+                });
+            }
+            bound(p, domain);
+        }
+    }
+    PressureStats {
+        iterations,
+        residual: error as f32,
+        time: start.elapsed(),
+    }
+}
+
+/// Project a divergent field with the given method, and with a disc of
+/// obstacle in the way when `obstacle`
+#[cfg(test)]
+fn project_with(method: PressureSolver, boundaries: Boundaries, obstacle: bool) -> PressureStats {
+    use crate::{project, swirling_divergent_field, Solver};
+
+    let shape = (34, 26);
+    let mut velocity = swirling_divergent_field(shape);
+    let mut solver = Solver::new(shape, boundaries);
+    solver.domain.pressure = method;
+    solver.domain.tolerance = 1e-4;
+    if obstacle {
+        solver.domain.obstacles.fill(Vec2::ZERO, |x, y| {
+            (x as f32 - 20.0).hypot(y as f32 - 10.0) < 4.0
+        });
+    }
+    project(&mut velocity, &mut solver, 20000)
+}
+
+#[test]
+fn test_pressure_solvers_converge() {
+    let open_right = Boundaries {
+        right: Boundary::Open,
+        ..Boundaries::default()
+    };
+    for (boundaries, obstacle) in [
+        (Boundaries::default(), false),
+        (Boundaries::default(), true),
+        (open_right, true),
+        (Boundaries::all(Boundary::Periodic), false),
+    ] {
+        let stats = PressureSolver::ALL.map(|method| project_with(method, boundaries, obstacle));
+        for (method, stats) in PressureSolver::ALL.iter().zip(&stats) {
+            assert!(stats.residual <= 1e-4, "{} {:?}", method.name(), stats);
+        }
+        // Jacobi, Gauss-Seidel, multigrid, PCG
+        let iterations = stats.map(|stats| stats.iterations);
+        assert!(iterations[1] < iterations[0], "{:?}", iterations);
+        assert!(
+            iterations[2] < 10 && iterations[2] * 50 < iterations[1],
+            "{:?}",
+            iterations
+        );
+        assert!(iterations[3] <= iterations[2], "{:?}", iterations);
+    }
+}
+
+#[test]
+fn test_pressure_stops_at_iterations() {
+    use crate::{project, swirling_divergent_field, Solver};
+
+    let mut velocity = swirling_divergent_field((34, 34));
+    let mut solver = Solver::new((34, 34), Boundaries::default());
+    let stats = project(&mut velocity, &mut solver, 6);
+    assert_eq!(stats.iterations, 6);
+    assert!(stats.residual > solver.domain.tolerance);
+}
@@ -0,0 +1,364 @@
+//! Fluid fields drawn into RGBA images
+//!
+//! Every pixel is worked out on the CPU instead of drawing a nannou shape
+//! per cell, so a frame can be uploaded as one texture or saved as a PNG
+//! without a window. The cells are upscaled bilinearly between their
+//! centres, and the first image row is the top of the grid.
+
+use std::borrow::Cow;
+
+use nannou::{
+    image::{ImageBuffer, RgbaImage},
+    prelude::{vec2, Rect, Vec2},
+};
+use ndarray::Array2;
+use rayon::prelude::*;
+use vector_field::{
+    lic::{stretch, Lic},
+    streamlines::Streamlines,
+    to_rect,
+};
+
+use crate::{
+    colormap::Gradient,
+    fluid_object::{hsv_to_rgb, DyeDisplay},
+    sample, FluidSim,
+};
+
+/// Colour of solid cells
+const SOLID: [f32; 3] = [0.5, 0.5, 0.5];
+
+/// Scalars that can be shown through a colour map
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Scalar {
+    /// The first dye channel
+    #[default]
+    Density,
+    Speed,
+    Curl,
+    Pressure,
+}
+
+impl Scalar {
+    pub const ALL: [Scalar; 4] = [
+        Scalar::Density,
+        Scalar::Speed,
+        Scalar::Curl,
+        Scalar::Pressure,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scalar::Density => "density",
+            Scalar::Speed => "speed",
+            Scalar::Curl => "curl",
+            Scalar::Pressure => "pressure",
+        }
+    }
+
+    /// Whether the scalar goes both ways, so suits a diverging map centred
+    /// on zero
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Scalar::Curl | Scalar::Pressure)
+    }
+}
+
+/// From zero to the largest value, or for signed fields from minus to
+/// plus the largest size, so zero stays in the middle of the map
+pub fn auto_range(values: &Array2<f32>, signed: bool) -> (f32, f32) {
+    if signed {
+        let max = values.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+        (-max, max)
+    } else {
+        (0.0, values.iter().fold(0.0f32, |max, &v| max.max(v)))
+    }
+}
+
+/// Colour of a sample of the first three dye channels, channels the
+/// fluid doesn't have are zero
+pub fn dye_color(display: DyeDisplay, channels: [f32; 3]) -> [f32; 3] {
+    match display {
+        DyeDisplay::Density(color) => hsv_to_rgb(color.hue, color.sat, channels[0].clamp(0.0, 1.0)),
+        DyeDisplay::Rgb => channels.map(|c| c.clamp(0.0, 1.0)),
+    }
+}
+
+/// Direction as the hue, starting from red pointing right and going
+/// anticlockwise, and speed as the brightness up to `max_speed`
+pub fn velocity_color(velocity: Vec2, max_speed: f32) -> [f32; 3] {
+    let (hue, speed) = hue_speed(velocity, max_speed);
+    hsv_to_rgb(hue, 1.0, speed)
+}
+
+/// Direction as a hue and speed as a fraction of `max_speed`
+fn hue_speed(velocity: Vec2, max_speed: f32) -> (f32, f32) {
+    let hue = velocity.y.atan2(velocity.x) / std::f32::consts::TAU;
+    let speed = if max_speed > 0.0 {
+        (velocity.length() / max_speed).min(1.0)
+    } else {
+        0.0
+    };
+    (hue, speed)
+}
+
+/// The dye channels as an image of `size` pixels
+pub fn render_dyes(
+    dyes: &[&Array2<f32>],
+    display: DyeDisplay,
+    solid: &Array2<bool>,
+    size: (u32, u32),
+) -> RgbaImage {
+    render(solid, size, |pos| {
+        let mut channels = [0.0; 3];
+        for (channel, dye) in channels.iter_mut().zip(dyes) {
+            *channel = sample(dye, pos);
+        }
+        dye_color(display, channels)
+    })
+}
+
+/// A scalar field as an image of `size` pixels, with `range` spread over
+/// the gradient
+pub fn render_scalar(
+    values: &Array2<f32>,
+    gradient: &Gradient,
+    range: (f32, f32),
+    solid: &Array2<bool>,
+    size: (u32, u32),
+) -> RgbaImage {
+    render(solid, size, |pos| {
+        gradient.map(sample(values, pos), range.0, range.1)
+    })
+}
+
+/// The velocity as an image of `size` pixels, coloured by
+/// `velocity_color`
+pub fn render_velocity(
+    velocity: &Array2<Vec2>,
+    max_speed: f32,
+    solid: &Array2<bool>,
+    size: (u32, u32),
+) -> RgbaImage {
+    render(solid, size, |pos| {
+        velocity_color(sample(velocity, pos), max_speed)
+    })
+}
+
+/// Streaks from a line integral convolution, `lic` in [0, 1] stretched
+/// over the grid, as an image of `size` pixels. The hue is the direction
+/// and the colour is grey where the fluid is still, saturating up to
+/// `max_speed`
+pub fn render_lic(
+    lic: &Array2<f32>,
+    velocity: &Array2<Vec2>,
+    max_speed: f32,
+    solid: &Array2<bool>,
+    size: (u32, u32),
+) -> RgbaImage {
+    let (nx, ny) = solid.dim();
+    let (lx, ly) = lic.dim();
+    let scale = vec2(lx as f32 / nx as f32, ly as f32 / ny as f32);
+    render(solid, size, |pos| {
+        let (hue, speed) = hue_speed(sample(velocity, pos), max_speed);
+        hsv_to_rgb(hue, speed, sample(lic, pos * scale).clamp(0.0, 1.0))
+    })
+}
+
+/// Fill an image with `color` at the position in cells under the centre
+/// of each pixel, in grey over the cells in `solid`
+fn render(
+    solid: &Array2<bool>,
+    size: (u32, u32),
+    color: impl Fn(Vec2) -> [f32; 3] + Sync,
+) -> RgbaImage {
+    let (nx, ny) = solid.dim();
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let scale = vec2(nx as f32 / width as f32, ny as f32 / height as f32);
+    let mut pixels = vec![0; 4 * width * height];
+    if width > 0 {
+        pixels
+            .par_chunks_mut(4 * width)
+            .enumerate()
+            .for_each(|(row, pixels)| {
+                let y = (height - 1 - row) as f32;
+                for (x, pixel) in pixels.chunks_mut(4).enumerate() {
+                    let pos = (vec2(x as f32, y) + 0.5) * scale;
+                    let cell = ((pos.x as usize).min(nx - 1), (pos.y as usize).min(ny - 1));
+                    let rgb = if solid[cell] { SOLID } else { color(pos) };
+                    for (byte, c) in pixel.iter_mut().zip(rgb) {
+                        *byte = (c * 255.0).round() as u8;
+                    }
+                    pixel[3] = u8::MAX;
+                }
+            });
+    }
+    ImageBuffer::from_raw(size.0, size.1, pixels).expect("buffer fits the image")
+}
+
+/// Images and streamlines of any fluid solver, built on what
+/// [`FluidSim`] exposes so the solvers only have to step the fluid
+pub trait FluidRender: FluidSim {
+    /// The dye as an image of `size` pixels, see `render`
+    fn render_dens(&self, size: (u32, u32), display: DyeDisplay) -> RgbaImage {
+        let dyes: Vec<_> = (0..self.channels()).map(|c| self.dye(c)).collect();
+        render_dyes(&dyes, display, &self.solid(), size)
+    }
+
+    /// A scalar as an image of `size` pixels through `gradient`,
+    /// spread over `range` or by default over `auto_range`
+    fn render_scalar(
+        &self,
+        scalar: Scalar,
+        gradient: &Gradient,
+        range: Option<(f32, f32)>,
+        size: (u32, u32),
+    ) -> RgbaImage {
+        let values = match scalar {
+            Scalar::Density => Cow::Borrowed(self.dye(0)),
+            Scalar::Speed => Cow::Owned(self.velocity().mapv(|v| v.length())),
+            Scalar::Curl => Cow::Borrowed(self.curl()),
+            Scalar::Pressure => Cow::Borrowed(self.pressure()),
+        };
+        let range = range.unwrap_or_else(|| auto_range(&values, scalar.is_signed()));
+        render_scalar(&values, gradient, range, &self.solid(), size)
+    }
+
+    /// The velocity as an image of `size` pixels, brightest at `max_speed`
+    fn render_vel(&self, size: (u32, u32), max_speed: f32) -> RgbaImage {
+        render_velocity(self.velocity(), max_speed, &self.solid(), size)
+    }
+
+    /// Line integral convolution of `noise` along the velocity as an
+    /// image of `size` pixels, the streaks coloured like `render_vel` but
+    /// fading to grey instead of black as the fluid slows
+    fn render_lic(
+        &self,
+        lic: &Lic,
+        noise: &Array2<f32>,
+        max_speed: f32,
+        size: (u32, u32),
+    ) -> RgbaImage {
+        let mut streaks = lic.convolve(self.velocity(), noise);
+        stretch(&mut streaks, 2.0);
+        render_lic(&streaks, self.velocity(), max_speed, &self.solid(), size)
+    }
+
+    /// Evenly spaced streamlines of the velocity around the solid cells,
+    /// in window coordinates
+    fn streamlines(&self, streamlines: &Streamlines, wrect: Rect) -> Vec<Vec<Vec2>> {
+        let dim = self.size();
+        streamlines
+            .trace(self.velocity(), Some(&self.solid()))
+            .into_iter()
+            .map(|line| line.into_iter().map(|p| to_rect(p, dim, wrect)).collect())
+            .collect()
+    }
+}
+
+impl<T: FluidSim + ?Sized> FluidRender for T {}
+
+#[test]
+fn test_dye_colors() {
+    use crate::fluid_object::DensColor;
+
+    let red = DyeDisplay::Density(DensColor::new(0.0, 1.0));
+    assert_eq!(dye_color(red, [0.5, 7.0, 7.0]), [0.5, 0.0, 0.0]);
+    assert_eq!(dye_color(red, [3.0, 0.0, 0.0]), [1.0, 0.0, 0.0]);
+    assert_eq!(dye_color(red, [-1.0, 0.0, 0.0]), [0.0, 0.0, 0.0]);
+    let grey = DyeDisplay::Density(DensColor::new(0.3, 0.0));
+    assert_eq!(dye_color(grey, [0.25, 0.0, 0.0]), [0.25; 3]);
+    assert_eq!(
+        dye_color(DyeDisplay::Rgb, [0.2, 1.5, -0.5]),
+        [0.2, 1.0, 0.0]
+    );
+}
+
+#[test]
+fn test_velocity_colors() {
+    assert_eq!(velocity_color(vec2(2.0, 0.0), 1.0), [1.0, 0.0, 0.0]);
+    assert_eq!(velocity_color(vec2(0.0, 0.5), 1.0), [0.25, 0.5, 0.0]);
+    let left = velocity_color(vec2(-1.0, 0.0), 1.0);
+    assert!((left[1] - 1.0).abs() < 1e-5 && left[2] > 0.999 && left[0] < 1e-5);
+    assert_eq!(velocity_color(vec2(1.0, 1.0), 0.0), [0.0; 3]);
+}
+
+#[test]
+fn test_render_upscales_bilinearly() {
+    let grey = DyeDisplay::Density(crate::fluid_object::DensColor::new(0.0, 0.0));
+    // dark at the bottom left, brighter to the right and up
+    let dye = Array2::from_shape_fn((2, 2), |(x, y)| x as f32 * 0.5 + y as f32 * 0.5);
+    let mut solid = Array2::from_elem((2, 2), false);
+    let image = render_dyes(&[&dye], grey, &solid, (4, 2));
+    assert_eq!(image.dimensions(), (4, 2));
+    let row = |y| (0..4).map(|x| image.get_pixel(x, y)[0]).collect::<Vec<_>>();
+    // pixel centres a quarter of a cell apart, clamped outside the centres
+    assert_eq!(row(1), [0, 32, 96, 128]);
+    // the first row is the top
+    assert_eq!(row(0), [128, 159, 223, 255]);
+    assert!(image
+        .pixels()
+        .all(|p| p[3] == 255 && p[0] == p[1] && p[1] == p[2]));
+
+    solid[(1, 1)] = true;
+    let image = render_dyes(&[&dye], grey, &solid, (4, 2));
+    assert_eq!(image.get_pixel(3, 0)[0], 128);
+    assert_eq!(image.get_pixel(1, 0)[0], 159);
+}
+
+#[test]
+fn test_render_saves_png() {
+    use crate::fluid_object::FluidCube;
+
+    let rect = nannou::prelude::Rect::from_w_h(100.0, 100.0);
+    let mut fluid = FluidCube::new((20, 20));
+    fluid.set_channels(3);
+    fluid.add_dye(vec2(10.0, 10.0), &[1.0, 0.5, 0.0], rect);
+    fluid.add_velocity(vec2(-20.0, 0.0), vec2(1.0, 1.0), rect);
+    let path = std::env::temp_dir().join(format!("cpu_v1_render_{}.png", std::process::id()));
+    for image in [
+        fluid.render_dens((64, 48), DyeDisplay::Rgb),
+        fluid.render_vel((64, 48), 1.0),
+    ] {
+        assert!(image.pixels().any(|p| p[0] > 0));
+        image.save(&path).unwrap();
+        let loaded = nannou::image::open(&path).unwrap().into_rgba8();
+        assert_eq!(loaded, image);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_signed_scalars_centre_on_zero() {
+    use crate::colormap::ColorMap;
+
+    let values = Array2::from_shape_fn((3, 2), |(x, _)| x as f32 - 1.0);
+    assert_eq!(auto_range(&values, false), (0.0, 1.0));
+    assert_eq!(auto_range(&values, true), (-1.0, 1.0));
+
+    let gradient = ColorMap::CoolWarm.gradient();
+    let solid = Array2::from_elem((3, 2), false);
+    let image = render_scalar(&values, &gradient, (-1.0, 1.0), &solid, (3, 1));
+    // one pixel a cell, the middle cell is zero and gets the grey middle
+    assert_eq!(image.get_pixel(1, 0)[0], 0xdd);
+    assert_eq!(image.get_pixel(2, 0).0[..3], [0xb4, 0x04, 0x26]);
+}
+
+#[test]
+fn test_lic_fades_to_grey_when_still() {
+    // fast to the right on the left half, still on the right half
+    let velocity = Array2::from_shape_fn(
+        (4, 2),
+        |(x, _)| {
+            if x < 2 {
+                vec2(1.0, 0.0)
+            } else {
+                Vec2::ZERO
+            }
+        },
+    );
+    let lic = Array2::from_elem((8, 4), 0.5);
+    let solid = Array2::from_elem((4, 2), false);
+    let image = render_lic(&lic, &velocity, 1.0, &solid, (4, 2));
+    assert_eq!(image.get_pixel(0, 0).0, [128, 0, 0, 255]);
+    assert_eq!(image.get_pixel(3, 1).0, [128, 128, 128, 255]);
+}
@@ -0,0 +1,540 @@
+//! Scripted runs of a `FluidCube` without a window
+//!
+//! A scenario sets up the grid and the solver and lists emitters, which
+//! pour dye and push the fluid from a point between a start and a stop
+//! time, optionally swinging their direction back and forth. The frames
+//! are rendered on the CPU. Nothing depends on the clock or a random
+//! seed, so a scenario runs the same every time.
+//!
+//! The file is lines of `key = value`, numbers separated by spaces, with
+//! `#` starting a comment. The settings come first, then each `[emitter]`
+//! line starts an emitter whose keys follow it:
+//!
+//! ```text
+//! size = 160 120
+//! steps = 400
+//! every = 2
+//!
+//! [emitter]
+//! position = 0.1 0.5
+//! direction = 1 0
+//! color = 1 0.4 0.1
+//! oscillation = 20 8
+//! ```
+
+use std::{f32::consts::TAU, fmt, fs, io, path::Path, str::FromStr};
+
+use nannou::{
+    image::RgbaImage,
+    prelude::{vec2, Rect, Vec2},
+};
+
+use crate::{
+    colormap::{ColorMap, Rgb},
+    fluid_object::{DyeDisplay, FluidCube},
+    render::{FluidRender, Scalar},
+    splat::Brush,
+    FluidSim,
+};
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "io error: {}", e),
+            ScenarioError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+/// What the frames show
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum View {
+    /// The dye in the emitters' colours
+    Dye,
+    /// A scalar through the scenario's colour map, with all the dye in
+    /// one channel
+    Scalar(Scalar),
+}
+
+impl View {
+    fn from_name(name: &str) -> Option<Self> {
+        if name == "dye" {
+            return Some(View::Dye);
+        }
+        Scalar::ALL
+            .into_iter()
+            .find(|scalar| scalar.name() == name)
+            .map(View::Scalar)
+    }
+}
+
+/// A source of dye and velocity. Positions and sizes are fractions of the
+/// domain, which is the whole grid with (0, 0) at the bottom left and its
+/// longest side 1 long, and times are in the solver's time units.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Emitter {
+    pub position: Vec2,
+    /// Way the fluid is pushed, needn't be a unit vector
+    pub direction: Vec2,
+    /// Velocity added per unit time at the centre
+    pub force: f32,
+    pub color: Rgb,
+    /// Dye added per unit time at the centre, times the colour
+    pub amount: f32,
+    pub radius: f32,
+    /// On from `start` until `stop`
+    pub start: f32,
+    pub stop: f32,
+    /// Degrees the direction swings either way, zero holds it still
+    pub swing: f32,
+    /// Time for a full swing there and back
+    pub period: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            position: vec2(0.5, 0.5),
+            direction: vec2(0.0, 1.0),
+            force: 1.0,
+            color: [1.0, 1.0, 1.0],
+            amount: 10.0,
+            radius: 0.05,
+            start: 0.0,
+            stop: f32::INFINITY,
+            swing: 0.0,
+            period: 1.0,
+        }
+    }
+}
+
+impl Emitter {
+    pub fn is_on(&self, time: f32) -> bool {
+        (self.start..self.stop).contains(&time)
+    }
+
+    /// Unit direction at `time`, swung by the oscillation
+    pub fn direction_at(&self, time: f32) -> Vec2 {
+        let phase = if self.period > 0.0 {
+            (TAU * (time - self.start) / self.period).sin()
+        } else {
+            0.0
+        };
+        let (sin, cos) = (self.swing * phase).to_radians().sin_cos();
+        let d = self.direction.normalize_or_zero();
+        vec2(cos * d.x - sin * d.y, sin * d.x + cos * d.y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    /// Cells along each axis, including the boundary ring
+    pub size: (usize, usize),
+    pub dt: f32,
+    /// Solver iterations each step
+    pub iterations: usize,
+    pub steps: usize,
+    /// Steps between frames
+    pub every: usize,
+    pub viscosity: f32,
+    pub diffusion: f32,
+    pub vorticity: f32,
+    /// Fractions of the velocity and dye lost per unit time
+    pub dissipation: (f32, f32),
+    /// Pixels of each frame
+    pub image: (u32, u32),
+    pub view: View,
+    pub color_map: ColorMap,
+    /// Values spread over the colour map, by default from the frame's
+    /// `render::auto_range`
+    pub range: Option<(f32, f32)>,
+    pub emitters: Vec<Emitter>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            size: (128, 128),
+            dt: 0.1,
+            iterations: 20,
+            steps: 100,
+            every: 1,
+            viscosity: 0.0,
+            diffusion: 0.0,
+            vorticity: 0.0,
+            dissipation: (0.0, 0.0),
+            image: (512, 512),
+            view: View::Dye,
+            color_map: ColorMap::default(),
+            range: None,
+            emitters: Vec::new(),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// The cube the scenario starts from
+    pub fn fluid(&self) -> FluidCube {
+        let mut fluid = FluidCube::new(self.size);
+        fluid.set_channels(match self.view {
+            View::Dye => 3,
+            View::Scalar(_) => 1,
+        });
+        fluid.set_vorticity(self.vorticity);
+        fluid.set_dissipation(self.dissipation.0, self.dissipation.1);
+        fluid
+    }
+
+    /// The domain in the coordinates the cube takes positions in, one per
+    /// cell
+    fn rect(&self) -> Rect {
+        Rect::from_w_h(self.size.0 as f32, self.size.1 as f32)
+    }
+
+    /// Add what the emitters put in over the step starting at `time`
+    pub fn emit(&self, fluid: &mut FluidCube, time: f32) {
+        let rect = self.rect();
+        let length = self.size.0.max(self.size.1) as f32;
+        for emitter in self.emitters.iter().filter(|e| e.is_on(time)) {
+            let brush = Brush {
+                radius: emitter.radius * length,
+                force: emitter.force * self.dt,
+                ..Brush::default()
+            };
+            let amount = emitter.amount * self.dt;
+            let amounts = match self.view {
+                View::Dye => emitter.color.map(|c| c * amount).to_vec(),
+                View::Scalar(_) => vec![amount],
+            };
+            let pos = rect.bottom_left() + emitter.position * length;
+            fluid.splat(pos, &brush, &amounts, emitter.direction_at(time), rect);
+        }
+    }
+
+    pub fn render(&self, fluid: &FluidCube) -> RgbaImage {
+        match self.view {
+            View::Dye => fluid.render_dens(self.image, DyeDisplay::Rgb),
+            View::Scalar(scalar) => {
+                fluid.render_scalar(scalar, &self.color_map.gradient(), self.range, self.image)
+            }
+        }
+    }
+
+    /// Run every step, calling `frame` with the number and image of each
+    /// frame, counting from zero, and stopping at the first error
+    pub fn run<E>(
+        &self,
+        mut frame: impl FnMut(usize, &RgbaImage) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut fluid = self.fluid();
+        let every = self.every.max(1);
+        for step in 0..self.steps {
+            self.emit(&mut fluid, step as f32 * self.dt);
+            fluid.step(self.viscosity, self.diffusion, self.dt, self.iterations);
+            if (step + 1).is_multiple_of(every) {
+                frame(step / every, &self.render(&fluid))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = ScenarioError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut scenario = Scenario::default();
+        let mut image = None;
+        let mut size_line = 0;
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| ScenarioError::Parse {
+                line: i + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[emitter]" {
+                scenario.emitters.push(Emitter::default());
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected key = value, got {}", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+            let values = Values {
+                value,
+                error: &error,
+            };
+            match scenario.emitters.last_mut() {
+                Some(emitter) => set_emitter(emitter, key, &values)?,
+                None => match key {
+                    "size" => {
+                        let [nx, ny] = values.numbers::<usize, 2>()?;
+                        if nx < 3 || ny < 3 {
+                            return Err(error("size is at least 3 by 3".to_string()));
+                        }
+                        scenario.size = (nx, ny);
+                        size_line = i + 1;
+                    }
+                    "dt" => {
+                        let dt: f32 = values.number()?;
+                        if !dt.is_finite() || dt < 0.0 {
+                            return Err(error(format!("dt {} isn't a time step", dt)));
+                        }
+                        scenario.dt = dt;
+                    }
+                    "iterations" | "steps" | "every" => {
+                        let count = values.number()?;
+                        if count == 0 {
+                            return Err(error(format!("{} is at least 1", key)));
+                        }
+                        match key {
+                            "iterations" => scenario.iterations = count,
+                            "steps" => scenario.steps = count,
+                            _ => scenario.every = count,
+                        }
+                    }
+                    "viscosity" => scenario.viscosity = values.number()?,
+                    "diffusion" => scenario.diffusion = values.number()?,
+                    "vorticity" => scenario.vorticity = values.number()?,
+                    "dissipation" => {
+                        let [velocity, dye] = values.numbers()?;
+                        scenario.dissipation = (velocity, dye);
+                    }
+                    "image" => {
+                        let [w, h] = values.numbers()?;
+                        if w == 0 || h == 0 {
+                            return Err(error("image is at least 1 by 1".to_string()));
+                        }
+                        image = Some((w, h));
+                    }
+                    "view" => {
+                        scenario.view = View::from_name(value)
+                            .ok_or_else(|| error(format!("unknown view {}", value)))?
+                    }
+                    "colormap" => {
+                        scenario.color_map = ColorMap::ALL
+                            .into_iter()
+                            .find(|map| map.name() == value)
+                            .ok_or_else(|| error(format!("unknown colour map {}", value)))?
+                    }
+                    "range" => {
+                        let [min, max] = values.numbers()?;
+                        scenario.range = Some((min, max));
+                    }
+                    _ => return Err(error(format!("unknown setting {}", key))),
+                },
+            }
+        }
+        // four pixels a cell unless it's given
+        let pixels = |cells: usize| u32::try_from(cells).ok()?.checked_mul(4);
+        scenario.image = match (image, pixels(scenario.size.0), pixels(scenario.size.1)) {
+            (Some(image), _, _) => image,
+            (None, Some(w), Some(h)) => (w, h),
+            _ => {
+                return Err(ScenarioError::Parse {
+                    line: size_line,
+                    message: "size too big for the default image, give an image size".to_string(),
+                })
+            }
+        };
+        Ok(scenario)
+    }
+}
+
+fn set_emitter<F>(emitter: &mut Emitter, key: &str, values: &Values<F>) -> Result<(), ScenarioError>
+where
+    F: Fn(String) -> ScenarioError,
+{
+    match key {
+        "position" => emitter.position = Vec2::from(values.numbers()?),
+        "direction" => emitter.direction = Vec2::from(values.numbers()?),
+        "force" => emitter.force = values.number()?,
+        "color" => emitter.color = values.numbers()?,
+        "amount" => emitter.amount = values.number()?,
+        "radius" => emitter.radius = values.number()?,
+        "start" => emitter.start = values.number()?,
+        "stop" => emitter.stop = values.number()?,
+        "oscillation" => {
+            let [swing, period] = values.numbers()?;
+            emitter.swing = swing;
+            emitter.period = period;
+        }
+        _ => return Err((values.error)(format!("unknown emitter setting {}", key))),
+    }
+    Ok(())
+}
+
+/// The value of a line, and how to report what's wrong with it
+struct Values<'a, F> {
+    value: &'a str,
+    error: F,
+}
+
+impl<F: Fn(String) -> ScenarioError> Values<'_, F> {
+    fn numbers<T: FromStr, const N: usize>(&self) -> Result<[T; N], ScenarioError> {
+        let parsed: Vec<T> = self
+            .value
+            .split_whitespace()
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| (self.error)(format!("not a number: {}", v)))
+            })
+            .collect::<Result<_, _>>()?;
+        let count = parsed.len();
+        parsed
+            .try_into()
+            .map_err(|_| (self.error)(format!("expected {} numbers, got {}", N, count)))
+    }
+
+    fn number<T: FromStr>(&self) -> Result<T, ScenarioError> {
+        let [n] = self.numbers()?;
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_parse_scenario() {
+    let scenario: Scenario = "
+        # two jets
+        size = 40 30
+        dt = 0.05
+        every = 3
+        dissipation = 0.1 0.2
+        view = curl
+        colormap = coolwarm
+        range = -1 1
+
+        [emitter]
+        position = 0.1 0.3   # left
+        direction = 1 0
+        color = 1 0 0.5
+        stop = 2
+        oscillation = 30 4
+        [emitter]
+        start = 1
+    "
+    .parse()
+    .unwrap();
+    assert_eq!(scenario.size, (40, 30));
+    assert_eq!(scenario.dt, 0.05);
+    assert_eq!(scenario.every, 3);
+    assert_eq!(scenario.iterations, 20);
+    assert_eq!(scenario.dissipation, (0.1, 0.2));
+    assert_eq!(scenario.image, (160, 120));
+    assert_eq!(scenario.view, View::Scalar(Scalar::Curl));
+    assert_eq!(scenario.color_map, ColorMap::CoolWarm);
+    assert_eq!(scenario.range, Some((-1.0, 1.0)));
+    assert_eq!(
+        scenario.emitters,
+        [
+            Emitter {
+                position: vec2(0.1, 0.3),
+                direction: vec2(1.0, 0.0),
+                color: [1.0, 0.0, 0.5],
+                stop: 2.0,
+                swing: 30.0,
+                period: 4.0,
+                ..Emitter::default()
+            },
+            Emitter {
+                start: 1.0,
+                ..Emitter::default()
+            }
+        ]
+    );
+
+    // a size too big for the default image is fine when one is given
+    let big: Scenario = "size = 40 2000000000\nimage = 100 100".parse().unwrap();
+    assert_eq!(big.image, (100, 100));
+
+    for (text, line) in [
+        ("size = 40", 1),
+        ("dt = 0.1\nsteps = many", 2),
+        ("view = sideways", 1),
+        ("\n\nwidth = 3", 3),
+        ("[emitter]\nsize = 3 3", 2),
+        ("size = 2 40", 1),
+        ("dt 0.1", 1),
+        ("size = 40 30\ndt = -0.1", 2),
+        ("dt = inf", 1),
+        ("dt = NaN", 1),
+        ("steps = -3", 1),
+        ("iterations = 0", 1),
+        ("steps = 0", 1),
+        ("\nevery = 0", 2),
+        ("image = 0 0", 1),
+        ("size = 40 30\nimage = 640 0", 2),
+        ("dt = 0.1\nsize = 40 2000000000", 2),
+    ] {
+        match text.parse::<Scenario>() {
+            Err(ScenarioError::Parse { line: l, .. }) => assert_eq!(l, line, "{}", text),
+            other => panic!("{} gave {:?}", text, other),
+        }
+    }
+}
+
+#[test]
+fn test_emitter_oscillates() {
+    let emitter = Emitter {
+        direction: vec2(2.0, 0.0),
+        start: 1.0,
+        stop: 5.0,
+        swing: 90.0,
+        period: 4.0,
+        ..Emitter::default()
+    };
+    assert!(!emitter.is_on(0.5) && emitter.is_on(1.0) && !emitter.is_on(5.0));
+    assert!(emitter.direction_at(1.0).distance(vec2(1.0, 0.0)) < 1e-5);
+    assert!(emitter.direction_at(2.0).distance(vec2(0.0, 1.0)) < 1e-5);
+    assert!(emitter.direction_at(4.0).distance(vec2(0.0, -1.0)) < 1e-5);
+}
+
+#[test]
+fn test_runs_are_identical() {
+    let scenario: Scenario = include_str!("../scenarios/jets.txt").parse().unwrap();
+    let short = Scenario {
+        size: (34, 26),
+        steps: 12,
+        every: 4,
+        image: (68, 52),
+        ..scenario
+    };
+    let run = || {
+        let mut frames = Vec::new();
+        short
+            .run(|number, image| {
+                frames.push((number, image.clone()));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        frames
+    };
+    let frames = run();
+    assert_eq!(frames.iter().map(|f| f.0).collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(frames[0].1.dimensions(), (68, 52));
+    // the dye has spread and the frames change
+    assert!(frames[2].1.pixels().any(|p| p.0[..3] != [0, 0, 0]));
+    assert_ne!(frames[1].1, frames[2].1);
+    assert_eq!(frames, run());
+}
@@ -0,0 +1,125 @@
+//! Soft round brush for putting dye and velocity into the fluid
+//!
+//! Modelled on pavel's `splat.wgsl`, each splat adds a Gaussian bump
+//! centred on a point, so the input blends in instead of poking single
+//! cells.
+
+use nannou::prelude::{vec2, Rect, Vec2};
+
+use crate::{grid_scale, FluidSim};
+
+/// Size and shape of a splat, the radius is in window coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Brush {
+    /// Distance from the centre beyond which nothing is added
+    pub radius: f32,
+    /// Multiplies the velocity a splat adds
+    pub force: f32,
+    /// How quickly the splat fades towards its edge, the weight at the
+    /// radius is `exp(-falloff)`
+    pub falloff: f32,
+}
+
+impl Brush {
+    /// Fraction of the full amount added at `distance` from the centre
+    pub fn weight(&self, distance: f32) -> f32 {
+        if distance > self.radius {
+            0.0
+        } else {
+            let r = distance / self.radius;
+            (-self.falloff * r * r).exp()
+        }
+    }
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            radius: 30.0,
+            force: 1.0,
+            falloff: 4.0,
+        }
+    }
+}
+
+/// Call `f` with the index and weight of each point of a grid the brush
+/// covers, centred on `centre` in cells. Point `(i, j)` is at `offset +
+/// (i, j)` in cells, which are `cell_size` in window coordinates, and only
+/// the points from `first` to `last` inclusive are visited.
+pub(crate) fn for_each_covered(
+    brush: &Brush,
+    centre: Vec2,
+    cell_size: Vec2,
+    offset: Vec2,
+    first: (usize, usize),
+    last: (usize, usize),
+    mut f: impl FnMut(usize, usize, f32),
+) {
+    let reach = brush.radius / cell_size;
+    let range = |centre: f32, reach: f32, first: usize, last: usize| {
+        let low = (centre - reach).floor().max(first as f32);
+        let high = (centre + reach).ceil().min(last as f32);
+        // empty when the brush misses the grid or isn't a number
+        if low <= high {
+            low as usize..high as usize + 1
+        } else {
+            1..1
+        }
+    };
+    let centre = centre - offset;
+    for x in range(centre.x, reach.x, first.0, last.0) {
+        for y in range(centre.y, reach.y, first.1, last.1) {
+            let distance = ((vec2(x as f32, y as f32) - centre) * cell_size).length();
+            let weight = brush.weight(distance);
+            if weight > 0.0 {
+                f(x, y, weight);
+            }
+        }
+    }
+}
+
+/// Brush strokes for any fluid solver, built on [`FluidSim::splat`]
+pub trait FluidStroke: FluidSim {
+    /// Splat along the line from `from` to `to`, the path the mouse took
+    /// over a step of `dt`, pushing the fluid the way it moved. Splats are
+    /// half a radius apart so fast drags leave no gaps, and `from` is left
+    /// out as the previous stroke ended there.
+    fn stroke(
+        &mut self,
+        from: Vec2,
+        to: Vec2,
+        dt: f32,
+        brush: &Brush,
+        amounts: &[f32],
+        rect: Rect,
+    ) {
+        let (nx, ny) = self.size();
+        let by = to - from;
+        let velocity = if dt > 0.0 {
+            by * vec2(nx as f32, ny as f32) / rect.wh() / (dt * grid_scale(&[nx, ny]))
+        } else {
+            Vec2::ZERO
+        };
+        let spacing = 0.5 * brush.radius;
+        let splats = if spacing > 0.0 {
+            ((by.length() / spacing).ceil() as usize).clamp(1, 1000)
+        } else {
+            1
+        };
+        for i in 1..=splats {
+            let pos = from + by * (i as f32 / splats as f32);
+            self.splat(pos, brush, amounts, velocity, rect);
+        }
+    }
+}
+
+impl<T: FluidSim + ?Sized> FluidStroke for T {}
+
+#[test]
+fn test_brush_weight() {
+    let brush = Brush::default();
+    assert_eq!(brush.weight(0.0), 1.0);
+    assert!((brush.weight(brush.radius) - (-4.0f32).exp()).abs() < 1e-6);
+    assert_eq!(brush.weight(brush.radius + 1.0), 0.0);
+    assert!(brush.weight(10.0) > brush.weight(20.0));
+}